@@ -0,0 +1,70 @@
+// Volume envelope used by the square and noise channels (NRx2).
+//
+// Bit 7-4 - Initial volume of envelope (0-0Fh) (0=No Sound)
+// Bit 3   - Envelope direction (0=Decrease, 1=Increase)
+// Bit 2-0 - Number of envelope sweep (n: 0-7) (0=Stop)
+pub struct Envelope {
+  register: u8,
+  volume: u8,
+  timer: u8,
+}
+
+impl Default for Envelope {
+  fn default() -> Envelope {
+    Envelope {
+      register: 0,
+      volume: 0,
+      timer: 0,
+    }
+  }
+}
+
+impl Envelope {
+  pub fn read(&self) -> u8 {
+    self.register
+  }
+
+  pub fn write(&mut self, value: u8) {
+    self.register = value;
+  }
+
+  // The DAC is powered off whenever the upper 5 bits are all 0,
+  // which also disables the channel.
+  pub fn dac_enabled(&self) -> bool {
+    self.register & 0xf8 != 0
+  }
+
+  pub fn volume(&self) -> u8 {
+    self.volume
+  }
+
+  fn period(&self) -> u8 {
+    self.register & 0x07
+  }
+
+  pub fn trigger(&mut self) {
+    self.volume = self.register >> 4;
+    self.timer = if self.period() == 0 { 8 } else { self.period() };
+  }
+
+  // Clocked at 64hz by the frame sequencer.
+  pub fn clock(&mut self) {
+    if self.period() == 0 {
+      return;
+    }
+
+    self.timer = self.timer.saturating_sub(1);
+    if self.timer > 0 {
+      return;
+    }
+    self.timer = self.period();
+
+    if self.register & 0x08 != 0 {
+      if self.volume < 15 {
+        self.volume += 1;
+      }
+    } else if self.volume > 0 {
+      self.volume -= 1;
+    }
+  }
+}
@@ -0,0 +1,44 @@
+// The length counter disables a channel once it has been clocked
+// down to zero, but only when the length enable bit in NRx4 is set.
+// It is clocked at 256hz by the frame sequencer.
+pub struct Length {
+  max: u16,
+  counter: u16,
+  pub enabled: bool,
+}
+
+impl Length {
+  pub fn new(max: u16) -> Length {
+    Length {
+      max: max,
+      counter: 0,
+      enabled: false,
+    }
+  }
+
+  // Loads the counter from the length register (NRx1).
+  pub fn load(&mut self, value: u8) {
+    self.counter = self.max - (value as u16 & (self.max - 1));
+  }
+
+  // A trigger only reloads the counter if it already ran out.
+  pub fn trigger(&mut self) {
+    if self.counter == 0 {
+      self.counter = self.max;
+    }
+  }
+
+  // Returns false when the channel should be disabled.
+  pub fn clock(&mut self) -> bool {
+    if self.enabled && self.counter > 0 {
+      self.counter -= 1;
+      return self.counter != 0;
+    }
+    true
+  }
+
+  pub fn reset(&mut self) {
+    self.counter = 0;
+    self.enabled = false;
+  }
+}
@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::i16;
+
+use super::CLOCK_RATE;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+// A stereo output of the mixer at the host sample rate.
+struct Output {
+  // Sum of all the outputs since the last sample was taken. Averaging
+  // them acts as a simple low-pass filter when downsampling.
+  sum_left: f32,
+  sum_right: f32,
+  // High-pass filter that removes the DC offset, like the capacitor
+  // on the real hardware.
+  capacitor_left: f32,
+  capacitor_right: f32,
+  samples: VecDeque<(i16, i16)>,
+}
+
+impl Default for Output {
+  fn default() -> Output {
+    Output {
+      sum_left: 0.0,
+      sum_right: 0.0,
+      capacitor_left: 0.0,
+      capacitor_right: 0.0,
+      samples: VecDeque::new(),
+    }
+  }
+}
+
+impl Output {
+  fn add(&mut self, left: f32, right: f32) {
+    self.sum_left += left;
+    self.sum_right += right;
+  }
+
+  // `scale` converts the averaged sum into -1.0 to 1.0 for each side.
+  fn push_sample(&mut self, scale: (f32, f32), charge_factor: f32, max_len: usize) {
+    let left = self.sum_left * scale.0;
+    let right = self.sum_right * scale.1;
+    self.sum_left = 0.0;
+    self.sum_right = 0.0;
+
+    let out_left = left - self.capacitor_left;
+    self.capacitor_left = left - out_left * charge_factor;
+    let out_right = right - self.capacitor_right;
+    self.capacitor_right = right - out_right * charge_factor;
+
+    // Only keep a limited amount of audio in case nobody is draining it.
+    if self.samples.len() >= max_len {
+      self.samples.pop_front();
+    }
+    self.samples.push_back((to_i16(out_left), to_i16(out_right)));
+  }
+
+  fn drain(&mut self, out: &mut Vec<(i16, i16)>) {
+    out.extend(self.samples.drain(..));
+  }
+}
+
+// Mixes the analog output of the four channels into stereo samples
+// at the host sample rate. Optionally each channel can also be mixed
+// on its own (stems), which is handy for recording.
+//
+// NR50 (0xff24) - Channel control / ON-OFF / Volume
+// Bit 7   - Output Vin to SO2 terminal (1=Enable)
+// Bit 6-4 - SO2 output level (volume)  (0-7)
+// Bit 3   - Output Vin to SO1 terminal (1=Enable)
+// Bit 2-0 - SO1 output level (volume)  (0-7)
+//
+// NR51 (0xff25) - Selection of Sound output terminal
+// Bit 7-4 - Output sound 4-1 to SO2 terminal (left)
+// Bit 3-0 - Output sound 4-1 to SO1 terminal (right)
+pub struct Mixer {
+  pub master_volume: u8,
+  pub panning: u8,
+
+  sample_rate: u32,
+  sample_timer: u32,
+  sum_count: u32,
+  charge_factor: f32,
+
+  main: Output,
+  stems: Option<Vec<Output>>,
+
+  // Only affects the main output, stems always get their channel.
+  pub muted: [bool; 4],
+  pub solo: [bool; 4],
+}
+
+impl Default for Mixer {
+  fn default() -> Mixer {
+    let mut m = Mixer {
+      master_volume: 0,
+      panning: 0,
+      sample_rate: 0,
+      sample_timer: 0,
+      sum_count: 0,
+      charge_factor: 0.0,
+      main: Output::default(),
+      stems: None,
+      muted: [false; 4],
+      solo: [false; 4],
+    };
+    m.set_sample_rate(DEFAULT_SAMPLE_RATE);
+    m
+  }
+}
+
+impl Mixer {
+  pub fn new() -> Mixer {
+    Mixer::default()
+  }
+
+  // Sets the host sample rate. A rate of 0 disables sample generation.
+  pub fn set_sample_rate(&mut self, rate: u32) {
+    self.sample_rate = rate;
+    self.sample_timer = 0;
+    self.sum_count = 0;
+    self.main = Output::default();
+    if self.stems.is_some() {
+      self.set_stems_enabled(true);
+    }
+    if rate != 0 {
+      self.charge_factor = 0.999958f32.powf(CLOCK_RATE as f32 / rate as f32);
+    }
+  }
+
+  pub fn sample_rate(&self) -> u32 {
+    self.sample_rate
+  }
+
+  pub fn set_stems_enabled(&mut self, enabled: bool) {
+    self.stems = if enabled {
+      Some((0..4).map(|_| Output::default()).collect())
+    } else {
+      None
+    };
+  }
+
+  // A channel is audible if it isn't muted, and either it is soloed
+  // or no channel is soloed.
+  fn audible(&self, channel: usize) -> bool {
+    if self.muted[channel] {
+      return false;
+    }
+    self.solo[channel] || !self.solo.iter().any(|&s| s)
+  }
+
+  // Takes the analog output (-1.0 to 1.0) of each channel and advances
+  // the mixer by the amount of clock cycles.
+  pub fn step(&mut self, outputs: [f32; 4], cycles: u32) {
+    if self.sample_rate == 0 {
+      return;
+    }
+
+    for (i, output) in outputs.iter().enumerate() {
+      let left = if self.panning & (0x10 << i) != 0 { *output } else { 0.0 };
+      let right = if self.panning & (0x01 << i) != 0 { *output } else { 0.0 };
+
+      if self.audible(i) {
+        self.main.add(left, right);
+      }
+      if let Some(ref mut stems) = self.stems {
+        stems[i].add(left, right);
+      }
+    }
+    self.sum_count += 1;
+
+    self.sample_timer += self.sample_rate * cycles;
+    if self.sample_timer >= CLOCK_RATE {
+      self.sample_timer -= CLOCK_RATE;
+      self.push_sample();
+    }
+  }
+
+  fn push_sample(&mut self) {
+    let count = self.sum_count as f32;
+    self.sum_count = 0;
+
+    // Each side is the sum of up to 4 channels and the volume is
+    // a multiplier of 1-8, so scale it back into -1.0 to 1.0.
+    let left_volume = ((self.master_volume >> 4) & 0x07) as f32 + 1.0;
+    let right_volume = (self.master_volume & 0x07) as f32 + 1.0;
+    let scale = (left_volume / count / 32.0, right_volume / count / 32.0);
+
+    // Only keep a second worth of audio.
+    let max_len = self.sample_rate as usize;
+
+    self.main.push_sample(scale, self.charge_factor, max_len);
+    if let Some(ref mut stems) = self.stems {
+      for stem in stems.iter_mut() {
+        stem.push_sample(scale, self.charge_factor, max_len);
+      }
+    }
+  }
+
+  pub fn drain_samples(&mut self, out: &mut Vec<(i16, i16)>) {
+    self.main.drain(out);
+  }
+
+  pub fn drain_stem_samples(&mut self, channel: usize, out: &mut Vec<(i16, i16)>) {
+    if let Some(ref mut stems) = self.stems {
+      stems[channel].drain(out);
+    }
+  }
+}
+
+fn to_i16(v: f32) -> i16 {
+  let v = if v > 1.0 {
+    1.0
+  } else if v < -1.0 {
+    -1.0
+  } else {
+    v
+  };
+  (v * i16::MAX as f32) as i16
+}
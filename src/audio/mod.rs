@@ -0,0 +1,316 @@
+mod envelope;
+mod length;
+mod mixer;
+mod noise;
+mod recorder;
+mod sequencer;
+mod square;
+mod wav;
+mod wave;
+
+use super::mem::MemoryIo;
+use self::mixer::Mixer;
+use self::noise::Noise;
+use self::sequencer::FrameSequencer;
+use self::square::Square;
+use self::wave::Wave;
+
+pub use self::mixer::DEFAULT_SAMPLE_RATE;
+pub use self::recorder::Recorder;
+pub use self::wav::WavWriter;
+
+// The gameboy clock rate (4.194304 MHz).
+pub const CLOCK_RATE: u32 = 4194304;
+
+// Number of clock cycles that pass for each system step.
+const CYCLES_PER_STEP: u32 = 4;
+
+pub struct Audio {
+  // NR52 bit 7. When powered off all the registers are cleared and
+  // are read only, except for NR52 and wave ram.
+  powered: bool,
+
+  channel1: Square,
+  channel2: Square,
+  channel3: Wave,
+  channel4: Noise,
+  frame_sequencer: FrameSequencer,
+  mixer: Mixer,
+}
+
+impl Default for Audio {
+  fn default() -> Audio {
+    Audio {
+      powered: false,
+      channel1: Square::with_sweep(),
+      channel2: Square::new(),
+      channel3: Wave::new(),
+      channel4: Noise::new(),
+      frame_sequencer: FrameSequencer::new(),
+      mixer: Mixer::new(),
+    }
+  }
+}
+
+impl Audio {
+  pub fn new() -> Audio {
+    Audio::default()
+  }
+
+  pub fn step(&mut self) {
+    if self.powered {
+      if let Some(step) = self.frame_sequencer.step(CYCLES_PER_STEP) {
+        match step {
+          0 | 4 => self.clock_length(),
+          2 | 6 => {
+            self.clock_length();
+            self.channel1.clock_sweep();
+          }
+          7 => {
+            self.channel1.clock_envelope();
+            self.channel2.clock_envelope();
+            self.channel4.clock_envelope();
+          }
+          _ => (),
+        }
+      }
+
+      self.channel1.step(CYCLES_PER_STEP);
+      self.channel2.step(CYCLES_PER_STEP);
+      self.channel3.step(CYCLES_PER_STEP);
+      self.channel4.step(CYCLES_PER_STEP);
+    }
+
+    let outputs = self.outputs();
+    self.mixer.step(outputs, CYCLES_PER_STEP);
+  }
+
+  fn clock_length(&mut self) {
+    self.channel1.clock_length();
+    self.channel2.clock_length();
+    self.channel3.clock_length();
+    self.channel4.clock_length();
+  }
+
+  // Converts the digital output of each channel to an analog value
+  // between -1.0 and 1.0. A channel with its DAC off outputs 0.
+  fn outputs(&self) -> [f32; 4] {
+    let dac = |enabled: bool, output: u8| if enabled {
+      output as f32 / 7.5 - 1.0
+    } else {
+      0.0
+    };
+
+    [dac(self.channel1.dac_enabled(), self.channel1.output()),
+     dac(self.channel2.dac_enabled(), self.channel2.output()),
+     dac(self.channel3.dac_enabled(), self.channel3.output()),
+     dac(self.channel4.dac_enabled(), self.channel4.output())]
+  }
+
+  fn set_powered(&mut self, powered: bool) {
+    if self.powered && !powered {
+      for addr in 0xff10..0xff26 {
+        self.write_u8(addr, 0).unwrap();
+      }
+    } else if !self.powered && powered {
+      self.frame_sequencer = FrameSequencer::new();
+    }
+    self.powered = powered;
+  }
+
+  pub fn set_sample_rate(&mut self, rate: u32) {
+    self.mixer.set_sample_rate(rate);
+  }
+
+  pub fn sample_rate(&self) -> u32 {
+    self.mixer.sample_rate()
+  }
+
+  pub fn drain_samples(&mut self, out: &mut Vec<(i16, i16)>) {
+    self.mixer.drain_samples(out);
+  }
+
+  // Stems are the output of each channel mixed on its own.
+  pub fn set_stems_enabled(&mut self, enabled: bool) {
+    self.mixer.set_stems_enabled(enabled);
+  }
+
+  pub fn drain_stem_samples(&mut self, channel: usize, out: &mut Vec<(i16, i16)>) {
+    self.mixer.drain_stem_samples(channel, out);
+  }
+
+  // Channels are numbered from 0 to 3. Muting and soloing only affects
+  // what is heard, the channels keep running.
+  pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+    self.mixer.muted[channel] = muted;
+  }
+
+  pub fn channel_muted(&self, channel: usize) -> bool {
+    self.mixer.muted[channel]
+  }
+
+  pub fn set_channel_solo(&mut self, channel: usize, solo: bool) {
+    self.mixer.solo[channel] = solo;
+  }
+
+  pub fn channel_solo(&self, channel: usize) -> bool {
+    self.mixer.solo[channel]
+  }
+}
+
+impl MemoryIo for Audio {
+  fn read_u8(&self, addr: u16) -> Result<u8, String> {
+    // println!("reading audio byte from: {:#04x}", addr);
+    match addr {
+      // channel 1: NR10-NR14
+      0xff10...0xff14 => Ok(self.channel1.read_reg(addr - 0xff10)),
+      // channel 2: NR21-NR24
+      0xff15...0xff19 => Ok(self.channel2.read_reg(addr - 0xff15)),
+      // channel 3: NR30-NR34
+      0xff1a...0xff1e => Ok(self.channel3.read_reg(addr - 0xff1a)),
+      // channel 4: NR41-NR44
+      0xff1f...0xff23 => Ok(self.channel4.read_reg(addr - 0xff1f)),
+      // NR50
+      0xff24 => Ok(self.mixer.master_volume),
+      // NR51
+      0xff25 => Ok(self.mixer.panning),
+      // NR52
+      0xff26 => {
+        let mut value = 0x70;
+        if self.powered {
+          value |= 0x80;
+        }
+        if self.channel1.enabled() {
+          value |= 0x01;
+        }
+        if self.channel2.enabled() {
+          value |= 0x02;
+        }
+        if self.channel3.enabled() {
+          value |= 0x04;
+        }
+        if self.channel4.enabled() {
+          value |= 0x08;
+        }
+        Ok(value)
+      }
+      // wave ram
+      0xff30...0xff3f => Ok(self.channel3.read_ram(addr - 0xff30)),
+      // unused
+      _ => Ok(0xff),
+    }
+  }
+
+  fn write_u8(&mut self, addr: u16, value: u8) -> Result<(), String> {
+    // println!("write audio byte to: {:#04x} {}", addr, value);
+    match addr {
+      // NR52
+      0xff26 => self.set_powered(value & 0x80 != 0),
+      // wave ram
+      0xff30...0xff3f => self.channel3.write_ram(addr - 0xff30, value),
+      // All other registers can't be written while powered off.
+      _ if !self.powered => (),
+      // channel 1: NR10-NR14
+      0xff10...0xff14 => self.channel1.write_reg(addr - 0xff10, value),
+      // channel 2: NR21-NR24
+      0xff15...0xff19 => self.channel2.write_reg(addr - 0xff15, value),
+      // channel 3: NR30-NR34
+      0xff1a...0xff1e => self.channel3.write_reg(addr - 0xff1a, value),
+      // channel 4: NR41-NR44
+      0xff1f...0xff23 => self.channel4.write_reg(addr - 0xff1f, value),
+      // NR50
+      0xff24 => self.mixer.master_volume = value,
+      // NR51
+      0xff25 => self.mixer.panning = value,
+      _ => (),
+    };
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::mem::MemoryIo;
+
+  // Runs the frame sequencer for the amount of 512hz ticks.
+  fn run_sequencer(audio: &mut Audio, ticks: u32) {
+    for _ in 0..(8192 * ticks / CYCLES_PER_STEP) {
+      audio.step();
+    }
+  }
+
+  #[test]
+  fn test_sweep_overflow_disables_channel() {
+    let mut audio = Audio::new();
+    audio.write_u8(0xff26, 0x80).unwrap();
+    // sweep period 1, increase, shift 1
+    audio.write_u8(0xff10, 0x11).unwrap();
+    audio.write_u8(0xff12, 0xf0).unwrap();
+    audio.write_u8(0xff13, 0x00).unwrap();
+    // trigger with frequency 0x500
+    audio.write_u8(0xff14, 0x85).unwrap();
+    assert_eq!(audio.read_u8(0xff26).unwrap() & 0x01, 0x01);
+
+    // The first sweep clock is on step 2 of the frame sequencer. The new
+    // frequency (0x780) passes, but the second overflow check does not.
+    run_sequencer(&mut audio, 2);
+    assert_eq!(audio.read_u8(0xff26).unwrap() & 0x01, 0x01);
+    run_sequencer(&mut audio, 1);
+    assert_eq!(audio.read_u8(0xff26).unwrap() & 0x01, 0x00);
+  }
+
+  #[test]
+  fn test_frame_sequencer_length_steps() {
+    let mut audio = Audio::new();
+    audio.write_u8(0xff26, 0x80).unwrap();
+    // length 62 leaves 2 clocks
+    audio.write_u8(0xff16, 0x3e).unwrap();
+    audio.write_u8(0xff17, 0xf0).unwrap();
+    audio.write_u8(0xff19, 0xc0).unwrap();
+
+    // Length is clocked on steps 0 and 2, but not on step 1.
+    run_sequencer(&mut audio, 2);
+    assert_eq!(audio.read_u8(0xff26).unwrap() & 0x02, 0x02);
+    run_sequencer(&mut audio, 1);
+    assert_eq!(audio.read_u8(0xff26).unwrap() & 0x02, 0x00);
+  }
+
+  #[test]
+  fn test_frame_sequencer_envelope_steps() {
+    let mut audio = Audio::new();
+    audio.write_u8(0xff26, 0x80).unwrap();
+    // 50% duty, volume 15, decrease every clock
+    audio.write_u8(0xff16, 0x80).unwrap();
+    audio.write_u8(0xff17, 0xf1).unwrap();
+    // The duty waveform advances every system step, so it is back at
+    // position 0 (high) on each sequencer tick.
+    audio.write_u8(0xff18, 0xff).unwrap();
+    audio.write_u8(0xff19, 0x87).unwrap();
+
+    // The envelope is only clocked on step 7.
+    run_sequencer(&mut audio, 7);
+    assert_eq!(audio.channel2.output(), 15);
+    run_sequencer(&mut audio, 1);
+    assert_eq!(audio.channel2.output(), 14);
+    run_sequencer(&mut audio, 7);
+    assert_eq!(audio.channel2.output(), 14);
+    run_sequencer(&mut audio, 1);
+    assert_eq!(audio.channel2.output(), 13);
+  }
+
+  #[test]
+  fn test_registers_read_only_when_powered_off() {
+    let mut audio = Audio::new();
+    audio.write_u8(0xff24, 0x77).unwrap();
+    assert_eq!(audio.read_u8(0xff24).unwrap(), 0x00);
+
+    audio.write_u8(0xff26, 0x80).unwrap();
+    audio.write_u8(0xff24, 0x77).unwrap();
+    assert_eq!(audio.read_u8(0xff24).unwrap(), 0x77);
+
+    audio.write_u8(0xff26, 0x00).unwrap();
+    assert_eq!(audio.read_u8(0xff24).unwrap(), 0x00);
+    assert_eq!(audio.read_u8(0xff26).unwrap(), 0x70);
+  }
+}
@@ -0,0 +1,184 @@
+use super::envelope::Envelope;
+use super::length::Length;
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Noise channel (NR41-NR44). White noise is produced by a linear
+// feedback shift register that is either 15 or 7 bits wide.
+//
+// NR43:
+// Bit 7-4 - Shift Clock Frequency (s)
+// Bit 3   - Counter Step/Width (0=15 bits, 1=7 bits)
+// Bit 2-0 - Dividing Ratio of Frequencies (r)
+pub struct Noise {
+  length: Length,
+  envelope: Envelope,
+  enabled: bool,
+  polynomial: u8,
+  lfsr: u16,
+  timer: u32,
+}
+
+impl Default for Noise {
+  fn default() -> Noise {
+    Noise {
+      length: Length::new(64),
+      envelope: Envelope::default(),
+      enabled: false,
+      polynomial: 0,
+      lfsr: 0x7fff,
+      timer: 0,
+    }
+  }
+}
+
+impl Noise {
+  pub fn new() -> Noise {
+    Noise::default()
+  }
+
+  pub fn enabled(&self) -> bool {
+    self.enabled
+  }
+
+  pub fn dac_enabled(&self) -> bool {
+    self.envelope.dac_enabled()
+  }
+
+  // Reads register NR40-NR44 where `reg` is the offset from NR40.
+  // There is no NR40, it always reads back as 0xff.
+  pub fn read_reg(&self, reg: u16) -> u8 {
+    match reg {
+      0 | 1 => 0xff,
+      2 => self.envelope.read(),
+      3 => self.polynomial,
+      4 => if self.length.enabled { 0xff } else { 0xbf },
+      _ => unreachable!(),
+    }
+  }
+
+  pub fn write_reg(&mut self, reg: u16, value: u8) {
+    match reg {
+      0 => (),
+      1 => self.length.load(value),
+      2 => {
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+          self.enabled = false;
+        }
+      }
+      3 => self.polynomial = value,
+      4 => {
+        self.length.enabled = value & 0x40 != 0;
+        if value & 0x80 != 0 {
+          self.trigger();
+        }
+      }
+      _ => unreachable!(),
+    }
+  }
+
+  fn width_mode(&self) -> bool {
+    self.polynomial & 0x08 != 0
+  }
+
+  // Number of clock cycles between each shift of the LFSR.
+  fn period(&self) -> u32 {
+    DIVISORS[(self.polynomial & 0x07) as usize] << (self.polynomial >> 4)
+  }
+
+  fn trigger(&mut self) {
+    self.enabled = self.envelope.dac_enabled();
+    self.length.trigger();
+    self.timer = self.period();
+    self.envelope.trigger();
+    self.lfsr = 0x7fff;
+  }
+
+  pub fn step(&mut self, mut cycles: u32) {
+    while cycles >= self.timer {
+      cycles -= self.timer;
+      self.timer = self.period();
+
+      // XOR the low two bits, shift right and put the result in
+      // bit 14 (and also bit 6 in 7-bit mode).
+      let bit = (self.lfsr & 0x01) ^ ((self.lfsr >> 1) & 0x01);
+      self.lfsr = (self.lfsr >> 1) | (bit << 14);
+      if self.width_mode() {
+        self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+      }
+    }
+    self.timer -= cycles;
+  }
+
+  pub fn clock_length(&mut self) {
+    if !self.length.clock() {
+      self.enabled = false;
+    }
+  }
+
+  pub fn clock_envelope(&mut self) {
+    self.envelope.clock();
+  }
+
+  pub fn output(&self) -> u8 {
+    if !self.enabled {
+      return 0;
+    }
+
+    // The output is the inverse of bit 0.
+    if self.lfsr & 0x01 == 0 {
+      self.envelope.volume()
+    } else {
+      0
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  // Triggers the channel with the shortest shift period (8 cycles).
+  fn trigger(nr43: u8) -> Noise {
+    let mut noise = Noise::new();
+    noise.write_reg(2, 0xf0);
+    noise.write_reg(3, nr43);
+    noise.write_reg(4, 0x80);
+    noise
+  }
+
+  #[test]
+  fn test_lfsr_15_bit() {
+    let mut noise = trigger(0x00);
+    noise.step(8);
+    assert_eq!(noise.lfsr, 0x3fff);
+
+    let mut shifts = 1;
+    while noise.lfsr != 0x7fff {
+      noise.step(8);
+      shifts += 1;
+    }
+    assert_eq!(shifts, 32767);
+  }
+
+  #[test]
+  fn test_lfsr_7_bit() {
+    let mut noise = trigger(0x08);
+    noise.step(8);
+    assert_eq!(noise.lfsr, 0x3fbf);
+
+    // Only the low 7 bits feed back, so the output repeats every 127
+    // shifts.
+    let mut outputs = vec![];
+    for _ in 0..127 {
+      outputs.push(noise.output());
+      noise.step(8);
+    }
+    for i in 0..127 {
+      assert_eq!(noise.output(), outputs[i]);
+      noise.step(8);
+    }
+    assert!(outputs.contains(&0) && outputs.contains(&15));
+  }
+}
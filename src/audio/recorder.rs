@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use super::super::system::SystemCtrl;
+use super::wav::WavWriter;
+
+type FileWriter = WavWriter<BufWriter<File>>;
+
+fn create_writer(path: &Path, sample_rate: u32) -> Result<FileWriter, String> {
+  let file = match File::create(path) {
+    Ok(f) => f,
+    Err(e) => return Err(format!("recorder: {}: {}", path.display(), e)),
+  };
+  WavWriter::new(BufWriter::new(file), sample_rate).map_err(|e| format!("recorder: {}", e))
+}
+
+// "music.wav" -> "music.ch1.wav"
+fn stem_path(path: &Path, channel: usize) -> PathBuf {
+  let name = path.file_stem()
+    .map(|s| s.to_string_lossy().into_owned())
+    .unwrap_or_else(String::new);
+  path.with_file_name(format!("{}.ch{}.wav", name, channel + 1))
+}
+
+// Records the mixed audio output into a wav file, and optionally each
+// channel into its own file next to it.
+pub struct Recorder {
+  mix: FileWriter,
+  stems: Vec<FileWriter>,
+  buffer: Vec<(i16, i16)>,
+}
+
+impl Recorder {
+  // Creates the wav files. If `stems` is true this also enables the
+  // per-channel stems on the system.
+  pub fn create<P, S>(path: P, system: &mut S, sample_rate: u32, stems: bool) -> Result<Recorder, String>
+    where P: AsRef<Path>,
+          S: SystemCtrl + ?Sized
+  {
+    let path = path.as_ref();
+    let mix = try!(create_writer(path, sample_rate));
+
+    let mut stem_writers = vec![];
+    if stems {
+      for channel in 0..4 {
+        stem_writers.push(try!(create_writer(&stem_path(path, channel), sample_rate)));
+      }
+    }
+    system.set_audio_stems(stems);
+
+    Ok(Recorder {
+      mix: mix,
+      stems: stem_writers,
+      buffer: vec![],
+    })
+  }
+
+  // Writes the mixed samples that the frontend drained from the system,
+  // and drains the stems from the system itself.
+  pub fn record<S>(&mut self, mix: &[(i16, i16)], system: &mut S) -> Result<(), String>
+    where S: SystemCtrl + ?Sized
+  {
+    try!(self.mix.write_samples(mix).map_err(|e| format!("recorder: {}", e)));
+
+    for (channel, stem) in self.stems.iter_mut().enumerate() {
+      self.buffer.clear();
+      system.drain_audio_stem_samples(channel, &mut self.buffer);
+      try!(stem.write_samples(&self.buffer).map_err(|e| format!("recorder: {}", e)));
+    }
+
+    Ok(())
+  }
+
+  pub fn finish(&mut self) -> Result<(), String> {
+    try!(self.mix.finish().map_err(|e| format!("recorder: {}", e)));
+    for stem in &mut self.stems {
+      try!(stem.finish().map_err(|e| format!("recorder: {}", e)));
+    }
+    Ok(())
+  }
+}
+
+impl Drop for Recorder {
+  fn drop(&mut self) {
+    let _ = self.finish();
+  }
+}
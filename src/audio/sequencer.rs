@@ -0,0 +1,67 @@
+// The frame sequencer runs at 512hz and generates the low frequency
+// clocks for the length, envelope and sweep units.
+//
+// Step   Length Ctr  Vol Env     Sweep
+// ---------------------------------------
+// 0      Clock       -           -
+// 1      -           -           -
+// 2      Clock       -           Clock
+// 3      -           -           -
+// 4      Clock       -           -
+// 5      -           -           -
+// 6      Clock       -           Clock
+// 7      -           Clock       -
+// ---------------------------------------
+// Rate   256 Hz      64 Hz       128 Hz
+
+// 4194304hz / 512hz
+const PERIOD: u32 = 8192;
+
+pub struct FrameSequencer {
+  timer: u32,
+  step: u8,
+}
+
+impl Default for FrameSequencer {
+  fn default() -> FrameSequencer {
+    FrameSequencer {
+      timer: PERIOD,
+      step: 0,
+    }
+  }
+}
+
+impl FrameSequencer {
+  pub fn new() -> FrameSequencer {
+    FrameSequencer::default()
+  }
+
+  // Advances the sequencer by the amount of clock cycles. Returns the
+  // step that should be run if the sequencer ticked.
+  pub fn step(&mut self, cycles: u32) -> Option<u8> {
+    if self.timer > cycles {
+      self.timer -= cycles;
+      return None;
+    }
+
+    self.timer += PERIOD - cycles;
+    let step = self.step;
+    self.step = (self.step + 1) & 0x07;
+    Some(step)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_step_order() {
+    let mut sequencer = FrameSequencer::new();
+    assert_eq!(sequencer.step(PERIOD - 4), None);
+    for i in 0..16 {
+      assert_eq!(sequencer.step(4), Some(i & 0x07));
+      assert_eq!(sequencer.step(PERIOD - 4), None);
+    }
+  }
+}
@@ -0,0 +1,334 @@
+use super::envelope::Envelope;
+use super::length::Length;
+
+// Waveforms for each duty cycle (NRx1 bits 7-6).
+const DUTY_PATTERNS: [[u8; 8]; 4] = [[0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+                                     [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+                                     [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+                                     [0, 1, 1, 1, 1, 1, 1, 0] /* 75% */];
+
+// Frequency sweep, only present on channel 1 (NR10).
+//
+// Bit 6-4 - Sweep period (n: 0-7) (0=Stop)
+// Bit 3   - Sweep direction (0=Increase, 1=Decrease)
+// Bit 2-0 - Number of sweep shift (n: 0-7)
+struct Sweep {
+  register: u8,
+  enabled: bool,
+  shadow: u16,
+  timer: u8,
+  negate_used: bool,
+}
+
+impl Default for Sweep {
+  fn default() -> Sweep {
+    Sweep {
+      register: 0,
+      enabled: false,
+      shadow: 0,
+      timer: 0,
+      negate_used: false,
+    }
+  }
+}
+
+impl Sweep {
+  fn period(&self) -> u8 {
+    (self.register >> 4) & 0x07
+  }
+
+  fn negate(&self) -> bool {
+    self.register & 0x08 != 0
+  }
+
+  fn shift(&self) -> u8 {
+    self.register & 0x07
+  }
+
+  fn reload_timer(&mut self) {
+    self.timer = if self.period() == 0 { 8 } else { self.period() };
+  }
+
+  // Calculates the next frequency from the shadow register. Returns None
+  // if the result overflowed past 2047, which disables the channel.
+  fn calculate(&mut self) -> Option<u16> {
+    let delta = self.shadow >> self.shift();
+    if self.negate() {
+      self.negate_used = true;
+      Some(self.shadow - delta)
+    } else {
+      let freq = self.shadow + delta;
+      if freq > 2047 { None } else { Some(freq) }
+    }
+  }
+
+  // Returns false if the channel should be disabled. Clearing the
+  // negate bit after a calculation was done in negate mode disables
+  // the channel.
+  fn write(&mut self, value: u8) -> bool {
+    let was_negate = self.negate();
+    self.register = value;
+    !(was_negate && !self.negate() && self.negate_used)
+  }
+}
+
+// Square wave channel. Channel 1 (NR10-NR14) has a frequency sweep,
+// channel 2 (NR21-NR24) does not.
+pub struct Square {
+  sweep: Option<Sweep>,
+  length: Length,
+  envelope: Envelope,
+  enabled: bool,
+  duty: u8,
+  duty_pos: u8,
+  frequency: u16,
+  timer: u32,
+}
+
+impl Default for Square {
+  fn default() -> Square {
+    Square {
+      sweep: None,
+      length: Length::new(64),
+      envelope: Envelope::default(),
+      enabled: false,
+      duty: 0,
+      duty_pos: 0,
+      frequency: 0,
+      timer: 0,
+    }
+  }
+}
+
+impl Square {
+  pub fn new() -> Square {
+    Square::default()
+  }
+
+  pub fn with_sweep() -> Square {
+    Square { sweep: Some(Sweep::default()), ..Square::default() }
+  }
+
+  pub fn enabled(&self) -> bool {
+    self.enabled
+  }
+
+  pub fn dac_enabled(&self) -> bool {
+    self.envelope.dac_enabled()
+  }
+
+  // Reads register NRx0-NRx4 where `reg` is the offset from NRx0.
+  // Write-only bits read back as 1.
+  pub fn read_reg(&self, reg: u16) -> u8 {
+    match reg {
+      0 => {
+        match self.sweep {
+          Some(ref sweep) => sweep.register | 0x80,
+          None => 0xff,
+        }
+      }
+      1 => self.duty << 6 | 0x3f,
+      2 => self.envelope.read(),
+      3 => 0xff,
+      4 => if self.length.enabled { 0xff } else { 0xbf },
+      _ => unreachable!(),
+    }
+  }
+
+  pub fn write_reg(&mut self, reg: u16, value: u8) {
+    match reg {
+      0 => {
+        if let Some(ref mut sweep) = self.sweep {
+          if !sweep.write(value) {
+            self.enabled = false;
+          }
+        }
+      }
+      1 => {
+        self.duty = value >> 6;
+        self.length.load(value);
+      }
+      2 => {
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+          self.enabled = false;
+        }
+      }
+      3 => self.frequency = (self.frequency & 0x700) | value as u16,
+      4 => {
+        self.frequency = (self.frequency & 0xff) | ((value as u16 & 0x07) << 8);
+        self.length.enabled = value & 0x40 != 0;
+        if value & 0x80 != 0 {
+          self.trigger();
+        }
+      }
+      _ => unreachable!(),
+    }
+  }
+
+  // Number of clock cycles between each step of the duty waveform.
+  fn period(&self) -> u32 {
+    (2048 - self.frequency as u32) * 4
+  }
+
+  fn trigger(&mut self) {
+    self.enabled = self.envelope.dac_enabled();
+    self.length.trigger();
+    self.timer = self.period();
+    self.envelope.trigger();
+
+    if let Some(ref mut sweep) = self.sweep {
+      sweep.shadow = self.frequency;
+      sweep.reload_timer();
+      sweep.negate_used = false;
+      sweep.enabled = sweep.period() != 0 || sweep.shift() != 0;
+      // The overflow check is done immediately if the shift is non-zero.
+      if sweep.shift() != 0 && sweep.calculate().is_none() {
+        self.enabled = false;
+      }
+    }
+  }
+
+  // Advances the frequency timer by the amount of clock cycles.
+  pub fn step(&mut self, mut cycles: u32) {
+    while cycles >= self.timer {
+      cycles -= self.timer;
+      self.timer = self.period();
+      self.duty_pos = (self.duty_pos + 1) & 0x07;
+    }
+    self.timer -= cycles;
+  }
+
+  // Clocked at 256hz by the frame sequencer.
+  pub fn clock_length(&mut self) {
+    if !self.length.clock() {
+      self.enabled = false;
+    }
+  }
+
+  // Clocked at 64hz by the frame sequencer.
+  pub fn clock_envelope(&mut self) {
+    self.envelope.clock();
+  }
+
+  // Clocked at 128hz by the frame sequencer.
+  pub fn clock_sweep(&mut self) {
+    if let Some(ref mut sweep) = self.sweep {
+      sweep.timer = sweep.timer.saturating_sub(1);
+      if sweep.timer > 0 {
+        return;
+      }
+      sweep.reload_timer();
+
+      if !sweep.enabled || sweep.period() == 0 {
+        return;
+      }
+
+      match sweep.calculate() {
+        Some(freq) => {
+          if sweep.shift() != 0 {
+            sweep.shadow = freq;
+            self.frequency = freq;
+            // The new frequency is run through the overflow check
+            // again, but it is not written back.
+            if sweep.calculate().is_none() {
+              self.enabled = false;
+            }
+          }
+        }
+        None => self.enabled = false,
+      }
+    }
+  }
+
+  // The current digital output of the channel (0-15).
+  pub fn output(&self) -> u8 {
+    if !self.enabled {
+      return 0;
+    }
+    DUTY_PATTERNS[self.duty as usize][self.duty_pos as usize] * self.envelope.volume()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  // Triggers the channel at the highest frequency, so the duty waveform
+  // advances every 4 clock cycles.
+  fn trigger(square: &mut Square, nr11: u8, nr12: u8, nr14: u8) {
+    square.write_reg(1, nr11);
+    square.write_reg(2, nr12);
+    square.write_reg(3, 0xff);
+    square.write_reg(4, 0x87 | nr14);
+  }
+
+  #[test]
+  fn test_duty_output() {
+    for duty in 0..4 {
+      let mut square = Square::new();
+      trigger(&mut square, duty << 6, 0xf0, 0);
+
+      let mut output = [0; 8];
+      for i in 0..8 {
+        output[i] = square.output() / 15;
+        square.step(4);
+      }
+      assert_eq!(output, DUTY_PATTERNS[duty as usize]);
+    }
+  }
+
+  #[test]
+  fn test_length_expiry() {
+    let mut square = Square::new();
+    // length 62 leaves 2 clocks
+    trigger(&mut square, 0x3e, 0xf0, 0x40);
+    assert!(square.enabled());
+    square.clock_length();
+    assert!(square.enabled());
+    square.clock_length();
+    assert!(!square.enabled());
+
+    // Without the length enable bit the counter is not clocked.
+    let mut square = Square::new();
+    trigger(&mut square, 0x3f, 0xf0, 0);
+    for _ in 0..64 {
+      square.clock_length();
+    }
+    assert!(square.enabled());
+  }
+
+  #[test]
+  fn test_envelope_stepping() {
+    let mut square = Square::new();
+    // volume 15, decrease every 2nd clock
+    trigger(&mut square, 0, 0xf2, 0);
+    assert_eq!(square.envelope.volume(), 15);
+    square.clock_envelope();
+    assert_eq!(square.envelope.volume(), 15);
+    square.clock_envelope();
+    assert_eq!(square.envelope.volume(), 14);
+    for _ in 0..28 {
+      square.clock_envelope();
+    }
+    assert_eq!(square.envelope.volume(), 0);
+    square.clock_envelope();
+    assert_eq!(square.envelope.volume(), 0);
+
+    // volume 14, increase every clock, stops at 15
+    let mut square = Square::new();
+    trigger(&mut square, 0, 0xe9, 0);
+    square.clock_envelope();
+    assert_eq!(square.envelope.volume(), 15);
+    square.clock_envelope();
+    assert_eq!(square.envelope.volume(), 15);
+
+    // period 0 never changes the volume
+    let mut square = Square::new();
+    trigger(&mut square, 0, 0xf0, 0);
+    for _ in 0..16 {
+      square.clock_envelope();
+    }
+    assert_eq!(square.envelope.volume(), 15);
+  }
+}
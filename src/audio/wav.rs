@@ -0,0 +1,88 @@
+use std::io::{self, Write, Seek, SeekFrom};
+
+const HEADER_LEN: u32 = 44;
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+
+fn write_u16<W: Write>(w: &mut W, v: u16) -> io::Result<()> {
+  w.write_all(&[v as u8, (v >> 8) as u8])
+}
+
+fn write_u32<W: Write>(w: &mut W, v: u32) -> io::Result<()> {
+  w.write_all(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8])
+}
+
+// Writes 16-bit stereo PCM wav files. The chunk sizes in the header
+// are only correct after `finish` is called.
+pub struct WavWriter<W: Write + Seek> {
+  inner: W,
+  data_len: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+  pub fn new(mut inner: W, sample_rate: u32) -> io::Result<WavWriter<W>> {
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+
+    try!(inner.write_all(b"RIFF"));
+    try!(write_u32(&mut inner, HEADER_LEN - 8));
+    try!(inner.write_all(b"WAVE"));
+
+    try!(inner.write_all(b"fmt "));
+    try!(write_u32(&mut inner, 16));
+    // PCM
+    try!(write_u16(&mut inner, 1));
+    try!(write_u16(&mut inner, CHANNELS));
+    try!(write_u32(&mut inner, sample_rate));
+    try!(write_u32(&mut inner, sample_rate * block_align as u32));
+    try!(write_u16(&mut inner, block_align));
+    try!(write_u16(&mut inner, BITS_PER_SAMPLE));
+
+    try!(inner.write_all(b"data"));
+    try!(write_u32(&mut inner, 0));
+
+    Ok(WavWriter {
+      inner: inner,
+      data_len: 0,
+    })
+  }
+
+  pub fn write_samples(&mut self, samples: &[(i16, i16)]) -> io::Result<()> {
+    for &(left, right) in samples {
+      try!(write_u16(&mut self.inner, left as u16));
+      try!(write_u16(&mut self.inner, right as u16));
+    }
+    self.data_len += samples.len() as u32 * 4;
+    Ok(())
+  }
+
+  // Updates the RIFF and data chunk sizes in the header.
+  pub fn finish(&mut self) -> io::Result<()> {
+    try!(self.inner.seek(SeekFrom::Start(4)));
+    try!(write_u32(&mut self.inner, HEADER_LEN - 8 + self.data_len));
+    try!(self.inner.seek(SeekFrom::Start(HEADER_LEN as u64 - 4)));
+    try!(write_u32(&mut self.inner, self.data_len));
+    try!(self.inner.seek(SeekFrom::End(0)));
+    self.inner.flush()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::io::Cursor;
+
+  #[test]
+  fn test_header_sizes() {
+    let mut w = WavWriter::new(Cursor::new(vec![]), 44100).unwrap();
+    w.write_samples(&[(1, -1), (0x1234, 0)]).unwrap();
+    w.finish().unwrap();
+
+    let data = w.inner.into_inner();
+    assert_eq!(data.len(), 44 + 8);
+    assert_eq!(&data[0..4], b"RIFF");
+    assert_eq!(&data[4..8], &[44, 0, 0, 0]);
+    assert_eq!(&data[24..28], &[0x44, 0xac, 0, 0]);
+    assert_eq!(&data[40..44], &[8, 0, 0, 0]);
+    assert_eq!(&data[44..52], &[1, 0, 0xff, 0xff, 0x34, 0x12, 0, 0]);
+  }
+}
@@ -0,0 +1,166 @@
+use super::length::Length;
+
+pub const WAVE_RAM_LEN: usize = 16;
+
+// Wave channel (NR30-NR34). Plays back the 32 4-bit samples stored in
+// wave ram at 0xff30-0xff3f, upper nibble first.
+pub struct Wave {
+  dac_enabled: bool,
+  length: Length,
+  enabled: bool,
+  volume_code: u8,
+  frequency: u16,
+  timer: u32,
+  position: u8,
+  ram: [u8; WAVE_RAM_LEN],
+}
+
+impl Default for Wave {
+  fn default() -> Wave {
+    Wave {
+      dac_enabled: false,
+      length: Length::new(256),
+      enabled: false,
+      volume_code: 0,
+      frequency: 0,
+      timer: 0,
+      position: 0,
+      ram: [0; WAVE_RAM_LEN],
+    }
+  }
+}
+
+impl Wave {
+  pub fn new() -> Wave {
+    Wave::default()
+  }
+
+  pub fn enabled(&self) -> bool {
+    self.enabled
+  }
+
+  pub fn dac_enabled(&self) -> bool {
+    self.dac_enabled
+  }
+
+  pub fn read_reg(&self, reg: u16) -> u8 {
+    match reg {
+      0 => if self.dac_enabled { 0xff } else { 0x7f },
+      1 => 0xff,
+      2 => self.volume_code << 5 | 0x9f,
+      3 => 0xff,
+      4 => if self.length.enabled { 0xff } else { 0xbf },
+      _ => unreachable!(),
+    }
+  }
+
+  pub fn write_reg(&mut self, reg: u16, value: u8) {
+    match reg {
+      0 => {
+        self.dac_enabled = value & 0x80 != 0;
+        if !self.dac_enabled {
+          self.enabled = false;
+        }
+      }
+      1 => self.length.load(value),
+      2 => self.volume_code = (value >> 5) & 0x03,
+      3 => self.frequency = (self.frequency & 0x700) | value as u16,
+      4 => {
+        self.frequency = (self.frequency & 0xff) | ((value as u16 & 0x07) << 8);
+        self.length.enabled = value & 0x40 != 0;
+        if value & 0x80 != 0 {
+          self.trigger();
+        }
+      }
+      _ => unreachable!(),
+    }
+  }
+
+  pub fn read_ram(&self, offset: u16) -> u8 {
+    self.ram[offset as usize]
+  }
+
+  pub fn write_ram(&mut self, offset: u16, value: u8) {
+    self.ram[offset as usize] = value;
+  }
+
+  // Number of clock cycles between each sample.
+  fn period(&self) -> u32 {
+    (2048 - self.frequency as u32) * 2
+  }
+
+  fn trigger(&mut self) {
+    self.enabled = self.dac_enabled;
+    self.length.trigger();
+    self.timer = self.period();
+    self.position = 0;
+  }
+
+  pub fn step(&mut self, mut cycles: u32) {
+    while cycles >= self.timer {
+      cycles -= self.timer;
+      self.timer = self.period();
+      self.position = (self.position + 1) & 0x1f;
+    }
+    self.timer -= cycles;
+  }
+
+  pub fn clock_length(&mut self) {
+    if !self.length.clock() {
+      self.enabled = false;
+    }
+  }
+
+  pub fn output(&self) -> u8 {
+    if !self.enabled {
+      return 0;
+    }
+
+    let byte = self.ram[(self.position / 2) as usize];
+    let sample = if self.position % 2 == 0 { byte >> 4 } else { byte & 0x0f };
+
+    // Volume code: 0 = mute, 1 = 100%, 2 = 50%, 3 = 25%
+    match self.volume_code {
+      0 => 0,
+      code => sample >> (code - 1),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  // Triggers the channel at the highest frequency, so the position
+  // advances every 2 clock cycles.
+  fn trigger(ram: &[u8], nr32: u8) -> Wave {
+    let mut wave = Wave::new();
+    for (i, &value) in ram.iter().enumerate() {
+      wave.write_ram(i as u16, value);
+    }
+    wave.write_reg(0, 0x80);
+    wave.write_reg(2, nr32);
+    wave.write_reg(3, 0xff);
+    wave.write_reg(4, 0x87);
+    wave
+  }
+
+  #[test]
+  fn test_nibble_order() {
+    let mut wave = trigger(&[0x12, 0x34], 0x20);
+    let mut output = [0; 4];
+    for i in 0..4 {
+      output[i] = wave.output();
+      wave.step(2);
+    }
+    assert_eq!(output, [0x1, 0x2, 0x3, 0x4]);
+  }
+
+  #[test]
+  fn test_volume_shift() {
+    assert_eq!(trigger(&[0xf0], 0x00).output(), 0);
+    assert_eq!(trigger(&[0xf0], 0x20).output(), 15);
+    assert_eq!(trigger(&[0xf0], 0x40).output(), 7);
+    assert_eq!(trigger(&[0xf0], 0x60).output(), 3);
+  }
+}
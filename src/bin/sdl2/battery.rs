@@ -0,0 +1,68 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use gameboy::system::SystemCtrl;
+
+// How often the save file is written while the game keeps changing
+// the battery backed ram.
+const FLUSH_INTERVAL_FRAMES: u32 = 60;
+
+// Keeps the battery backed cartridge ram in sync with a save file next
+// to the rom ("game.gb" -> "game.sav"), in the raw format other
+// emulators use.
+pub struct BatterySave {
+  path: PathBuf,
+  frames: u32,
+}
+
+impl BatterySave {
+  pub fn new<P: AsRef<Path>>(rom_path: P) -> BatterySave {
+    BatterySave {
+      path: rom_path.as_ref().with_extension("sav"),
+      frames: 0,
+    }
+  }
+
+  // Loads the save file into the system if there is one.
+  pub fn load<S: SystemCtrl + ?Sized>(&self, system: &mut S) -> Result<(), String> {
+    let mut file = match File::open(&self.path) {
+      Ok(f) => f,
+      Err(_) => return Ok(()),
+    };
+
+    let mut data = vec![];
+    try!(file.read_to_end(&mut data).map_err(|e| format!("{}: {}", self.path.display(), e)));
+    try!(system.load_battery_ram(&data));
+    info!("loaded save file {}", self.path.display());
+    Ok(())
+  }
+
+  // Called once a frame, periodically writes the save file if the ram
+  // has been written to.
+  pub fn frame<S: SystemCtrl + ?Sized>(&mut self, system: &mut S) -> Result<(), String> {
+    self.frames += 1;
+    if self.frames < FLUSH_INTERVAL_FRAMES {
+      return Ok(());
+    }
+    self.frames = 0;
+
+    if system.battery_ram_dirty() {
+      self.flush(system)
+    } else {
+      Ok(())
+    }
+  }
+
+  // Writes the save file, does nothing for carts without a battery.
+  pub fn flush<S: SystemCtrl + ?Sized>(&self, system: &mut S) -> Result<(), String> {
+    let data = match system.save_battery_ram() {
+      Some(data) => data,
+      None => return Ok(()),
+    };
+
+    let mut file = try!(File::create(&self.path)
+      .map_err(|e| format!("{}: {}", self.path.display(), e)));
+    file.write_all(&data).map_err(|e| format!("{}: {}", self.path.display(), e))
+  }
+}
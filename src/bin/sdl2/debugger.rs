@@ -7,15 +7,29 @@ use std::rc::Rc;
 use gameboy::debugger::Debugger;
 use gameboy::cpu::Cpu;
 
+use battery::BatterySave;
+
 extern "C" {
   pub static stdout: *mut libc::FILE;
   pub static stderr: *mut libc::FILE;
   pub static stdin: *mut libc::FILE;
 }
 
-pub fn run_debugger(cpu: Cpu) {
+fn flush_battery(battery: &BatterySave, cpu: &mut Cpu) {
+  if let Err(e) = battery.flush(&mut *cpu.system) {
+    error!("couldn't write save file: {}", e);
+  }
+}
+
+// Runs until the exit command or end of input, and writes the save file
+// either way.
+pub fn run_debugger(cpu: Cpu, battery: BatterySave) {
+  let battery = Rc::new(battery);
   let mut debugger = Debugger::new(cpu);
 
+  let exit_battery = battery.clone();
+  debugger.set_exit_callback(move |cpu| flush_battery(&exit_battery, cpu));
+
   unsafe {
     libc::setbuf(stdout as *mut libc::FILE, 0 as *mut i8);
     libc::setbuf(stderr as *mut libc::FILE, 0 as *mut i8);
@@ -35,6 +49,8 @@ pub fn run_debugger(cpu: Cpu) {
     reader.add_history(line.to_owned());
     debugger.run_cmd(line.to_owned());
   }
+
+  flush_battery(&battery, &mut debugger.cpu);
 }
 
 struct CmdCompleter;
@@ -50,8 +66,10 @@ impl<Term: Terminal> Completer<Term> for CmdCompleter {
 
     let mut words = line[..start].split_whitespace();
 
-    let cmds = vec!["break",
+    let cmds = vec!["audio",
+    "break",
     "breakpoints",
+    "cheat",
     "continue",
     "debug",
     "exit",
@@ -0,0 +1,192 @@
+use gameboy::cartridge::{CartridgeHeader, CgbFlag, Destination, ROM_BANK_SIZE, RAM_BANK_SIZE};
+
+fn json_string(s: &str) -> String {
+  let mut out = String::with_capacity(s.len() + 2);
+  out.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+  out
+}
+
+fn json_option(s: &Option<String>) -> String {
+  match *s {
+    Some(ref s) => json_string(s),
+    None => "null".to_owned(),
+  }
+}
+
+fn cgb_name(flag: CgbFlag) -> &'static str {
+  match flag {
+    CgbFlag::DmgOnly => "dmg",
+    CgbFlag::CgbSupported => "supported",
+    CgbFlag::CgbOnly => "cgb only",
+  }
+}
+
+fn destination_name(destination: Destination) -> String {
+  match destination {
+    Destination::Japanese => "japanese".to_owned(),
+    Destination::Overseas => "overseas".to_owned(),
+    Destination::Unknown(v) => format!("unknown ({:#04x})", v),
+  }
+}
+
+// Everything `--info` reports about a rom.
+struct RomInfo {
+  header: CartridgeHeader,
+  cart_type: Option<String>,
+  mapper: Option<&'static str>,
+  supported: bool,
+  battery: bool,
+  timer: bool,
+  rumble: bool,
+  // From the header, None if the size is unknown.
+  rom_banks: Option<usize>,
+  ram_banks: Option<usize>,
+  // Actually in the file.
+  file_banks: usize,
+}
+
+impl RomInfo {
+  fn new(rom: &[u8]) -> Result<RomInfo, String> {
+    let header = try!(CartridgeHeader::parse(rom).map_err(|e| e.to_string()));
+    let cart_type = header.cart_type();
+    let rom_banks = header.rom_size().map(|s| s.banks());
+    // 2KB of ram still takes up a (partial) bank.
+    let ram_banks = header.ram_size().map(|s| (s.as_usize() + RAM_BANK_SIZE - 1) / RAM_BANK_SIZE);
+
+    Ok(RomInfo {
+      cart_type: cart_type.map(|t| format!("{:?}", t)),
+      mapper: cart_type.map(|t| t.mapper_name()),
+      supported: cart_type.map_or(false, |t| t.is_supported()),
+      battery: cart_type.map_or(false, |t| t.has_battery()),
+      timer: cart_type.map_or(false, |t| t.has_timer()),
+      rumble: cart_type.map_or(false, |t| t.has_rumble()),
+      rom_banks: rom_banks,
+      ram_banks: ram_banks,
+      file_banks: (rom.len() + ROM_BANK_SIZE - 1) / ROM_BANK_SIZE,
+      header: header,
+    })
+  }
+
+  fn print_text(&self) {
+    let h = &self.header;
+    let unknown = "unknown".to_owned();
+
+    println!("Title:            {}", h.title);
+    if let Some(ref code) = h.manufacturer_code {
+      println!("Manufacturer:     {}", code);
+    }
+    match h.new_licensee_code {
+      Some(ref code) => println!("Licensee:         {} (new)", code),
+      None => println!("Licensee:         {:#04x} (old)", h.old_licensee_code),
+    }
+    println!("CGB:              {}", cgb_name(h.cgb_flag));
+    println!("SGB:              {}", if h.sgb_flag { "yes" } else { "no" });
+    println!("Cartridge type:   {:#04x} ({})",
+             h.cart_type,
+             self.cart_type.as_ref().unwrap_or(&unknown));
+    println!("Mapper:           {} ({})",
+             self.mapper.unwrap_or("unknown"),
+             if self.supported { "supported" } else { "unsupported" });
+
+    let mut features = vec![];
+    if self.battery {
+      features.push("battery");
+    }
+    if self.timer {
+      features.push("timer");
+    }
+    if self.rumble {
+      features.push("rumble");
+    }
+    if !features.is_empty() {
+      println!("Features:         {}", features.join(", "));
+    }
+
+    match self.rom_banks {
+      Some(banks) => {
+        println!("ROM banks:        {} (file has {})", banks, self.file_banks)
+      }
+      None => {
+        println!("ROM banks:        unknown ({:#04x}, file has {})",
+                 h.rom_size,
+                 self.file_banks)
+      }
+    }
+    match self.ram_banks {
+      Some(banks) => println!("RAM banks:        {}", banks),
+      None => println!("RAM banks:        unknown ({:#04x})", h.ram_size),
+    }
+    println!("Destination:      {}", destination_name(h.destination));
+    println!("Version:          {}", h.version);
+    println!("Header checksum:  {:#04x} ({})",
+             h.header_checksum,
+             if h.header_checksum_valid() { "ok" } else { "bad" });
+    println!("Global checksum:  {:#06x} ({})",
+             h.global_checksum,
+             if h.global_checksum_valid() { "ok" } else { "bad" });
+    println!("Logo:             {}", if h.logo_valid() { "ok" } else { "bad" });
+
+    for warning in &h.warnings {
+      println!("Warning:          {}", warning);
+    }
+  }
+
+  fn print_json(&self) {
+    let h = &self.header;
+    let opt_num = |v: Option<usize>| v.map_or("null".to_owned(), |v| v.to_string());
+
+    let mut fields = vec![];
+    fields.push(format!("\"title\": {}", json_string(&h.title)));
+    fields.push(format!("\"manufacturer_code\": {}", json_option(&h.manufacturer_code)));
+    fields.push(format!("\"new_licensee_code\": {}", json_option(&h.new_licensee_code)));
+    fields.push(format!("\"old_licensee_code\": {}", h.old_licensee_code));
+    fields.push(format!("\"cgb\": {}", json_string(cgb_name(h.cgb_flag))));
+    fields.push(format!("\"sgb\": {}", h.sgb_flag));
+    fields.push(format!("\"cart_type\": {}", h.cart_type));
+    fields.push(format!("\"cart_type_name\": {}", json_option(&self.cart_type)));
+    fields.push(format!("\"mapper\": {}",
+                        json_option(&self.mapper.map(|m| m.to_owned()))));
+    fields.push(format!("\"supported\": {}", self.supported));
+    fields.push(format!("\"battery\": {}", self.battery));
+    fields.push(format!("\"timer\": {}", self.timer));
+    fields.push(format!("\"rumble\": {}", self.rumble));
+    fields.push(format!("\"rom_size\": {}", h.rom_size));
+    fields.push(format!("\"rom_banks\": {}", opt_num(self.rom_banks)));
+    fields.push(format!("\"file_banks\": {}", self.file_banks));
+    fields.push(format!("\"ram_size\": {}", h.ram_size));
+    fields.push(format!("\"ram_banks\": {}", opt_num(self.ram_banks)));
+    fields.push(format!("\"destination\": {}",
+                        json_string(&destination_name(h.destination))));
+    fields.push(format!("\"version\": {}", h.version));
+    fields.push(format!("\"header_checksum\": {}", h.header_checksum));
+    fields.push(format!("\"header_checksum_valid\": {}", h.header_checksum_valid()));
+    fields.push(format!("\"global_checksum\": {}", h.global_checksum));
+    fields.push(format!("\"global_checksum_valid\": {}", h.global_checksum_valid()));
+    fields.push(format!("\"logo_valid\": {}", h.logo_valid()));
+    let warnings: Vec<String> = h.warnings.iter().map(|w| json_string(&w.to_string())).collect();
+    fields.push(format!("\"warnings\": [{}]", warnings.join(", ")));
+
+    println!("{{{}}}", fields.join(", "));
+  }
+}
+
+// Prints what we know about a rom, as text or as a single line of json.
+pub fn print_info(rom: &[u8], json: bool) -> Result<(), String> {
+  let info = try!(RomInfo::new(rom));
+  if json {
+    info.print_json();
+  } else {
+    info.print_text();
+  }
+  Ok(())
+}
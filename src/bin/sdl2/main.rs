@@ -1,4 +1,5 @@
 extern crate sdl2;
+extern crate sdl2_sys;
 extern crate gameboy;
 extern crate clap;
 #[macro_use]
@@ -10,7 +11,7 @@ extern crate linefeed;
 
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use std::process::exit;
 
@@ -19,15 +20,27 @@ use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::rect::Rect;
-use clap::{Arg, App};
+use clap::{Arg, App, ArgMatches};
 use simplelog::{TermLogger, LogLevelFilter};
 
 use gameboy::cpu::Cpu;
-use gameboy::system;
+use gameboy::system::{self, SystemCtrl};
 use gameboy::gamepad::Button;
 use gameboy::disassembler;
+use gameboy::loader;
+use gameboy::patch;
+use gameboy::audio::{Recorder, DEFAULT_SAMPLE_RATE};
+use gameboy::cartridge::{CameraSource, CartridgeHeader, RtcMode};
 
+use battery::BatterySave;
+use pacer::{Pacer, AudioPacer, ClockPacer, FRAME_CYCLES};
+use rumble::Rumble;
+
+mod battery;
 mod debugger;
+mod info;
+mod pacer;
+mod rumble;
 
 macro_rules! try_log {
   ($expr:expr) => (match $expr {
@@ -39,13 +52,65 @@ macro_rules! try_log {
   })
 }
 
-fn load_rom<P: AsRef<Path>>(path: P) -> Box<[u8]> {
+fn read_file<P: AsRef<Path>>(path: P) -> Box<[u8]> {
   let mut file = try_log!(File::open(path));
   let mut file_buf = Vec::new();
   try_log!(file.read_to_end(&mut file_buf));
   file_buf.into_boxed_slice()
 }
 
+// Applies the patches given with --patch, or if there are none, the
+// first patch found next to the rom with the same name ("game.ips",
+// "game.ups" or "game.bps").
+fn apply_patches(rom_path: &str, rom: Box<[u8]>, matches: &ArgMatches) -> Box<[u8]> {
+  let paths: Vec<PathBuf> = match matches.values_of("patch") {
+    Some(values) => values.map(PathBuf::from).collect(),
+    None => {
+      let exts = ["ips", "ups", "bps"];
+      let mut found = exts.iter()
+        .map(|ext| Path::new(rom_path).with_extension(ext))
+        .filter(|path| path.is_file());
+
+      // Each of them patches the original rom, so applying more than one
+      // would corrupt it.
+      let first: Vec<PathBuf> = found.next().into_iter().collect();
+      for path in found {
+        warn!("ignoring patch {}, only {} is applied",
+              path.display(),
+              first[0].display());
+      }
+      first
+    }
+  };
+
+  let mut rom = rom.into_vec();
+  for path in paths {
+    let data = read_file(&path);
+    rom = try_log!(patch::apply(&rom, &data).map_err(|e| format!("{}: {}", path.display(), e)));
+    info!("applied patch {}", path.display());
+  }
+  rom.into_boxed_slice()
+}
+
+// Loads the cheats in the .cht file next to the rom ("game.cht").
+fn load_cheats<S: SystemCtrl + ?Sized>(rom_path: &str, system: &mut S) {
+  let path = Path::new(rom_path).with_extension("cht");
+  let mut file = match File::open(&path) {
+    Ok(f) => f,
+    Err(_) => return,
+  };
+
+  let mut text = String::new();
+  if let Err(e) = file.read_to_string(&mut text) {
+    warn!("couldn't read {}: {}", path.display(), e);
+    return;
+  }
+  match system.load_cheats(&text) {
+    Ok(n) => info!("loaded {} cheats from {}", n, path.display()),
+    Err(e) => warn!("couldn't load {}: {}", path.display(), e),
+  }
+}
+
 fn main() {
   TermLogger::init(LogLevelFilter::Info).unwrap();
 
@@ -67,6 +132,58 @@ fn main() {
       .long("disassemble")
       .use_delimiter(false)
       .help("Disassemble the file"))
+    .arg(Arg::with_name("info")
+      .long("info")
+      .use_delimiter(false)
+      .conflicts_with("disassemble")
+      .help("Print the cartridge header and exit"))
+    .arg(Arg::with_name("json")
+      .long("json")
+      .use_delimiter(false)
+      .requires("info")
+      .help("Print --info as json"))
+    .arg(Arg::with_name("no-audio")
+      .long("no-audio")
+      .use_delimiter(false)
+      .help("Disable audio output"))
+    .arg(Arg::with_name("record-audio")
+      .long("record-audio")
+      .use_delimiter(false)
+      .value_name("FILE")
+      .help("Record the audio output to a wav file")
+      .takes_value(true))
+    .arg(Arg::with_name("record-stems")
+      .long("record-stems")
+      .use_delimiter(false)
+      .requires("record-audio")
+      .help("Also record each audio channel to its own wav file next to FILE"))
+    .arg(Arg::with_name("frames")
+      .long("frames")
+      .use_delimiter(false)
+      .value_name("N")
+      .help("Emulate N frames without opening a window and exit")
+      .takes_value(true))
+    .arg(Arg::with_name("rtc")
+      .long("rtc")
+      .use_delimiter(false)
+      .value_name("MODE")
+      .possible_values(&["wallclock", "emulated"])
+      .help("Where the cartridge real time clock gets its time from")
+      .takes_value(true))
+    .arg(Arg::with_name("camera")
+      .long("camera")
+      .use_delimiter(false)
+      .value_name("FILE")
+      .help("A png or pgm image the Game Boy Camera sees instead of a real camera")
+      .takes_value(true))
+    .arg(Arg::with_name("patch")
+      .long("patch")
+      .use_delimiter(false)
+      .value_name("FILE")
+      .help("Apply an IPS, UPS or BPS patch to the rom, can be given multiple times")
+      .takes_value(true)
+      .multiple(true)
+      .number_of_values(1))
     .arg(Arg::with_name("boot-rom")
       .short("b")
       .long("boot-rom")
@@ -76,34 +193,139 @@ fn main() {
       .takes_value(true))
     .get_matches();
 
-  let cart_rom = load_rom(matches.value_of("cart-rom").unwrap());
+  let cart_path = matches.value_of("cart-rom").unwrap();
+  let cart_rom = apply_patches(cart_path, try_log!(loader::load_rom(cart_path)), &matches);
 
   if matches.is_present("disassemble") {
     disassembler::dump_all(cart_rom);
+  } else if matches.is_present("info") {
+    try_log!(info::print_info(&cart_rom, matches.is_present("json")));
   } else {
     let system = system::System::new();
     let mut cpu = Cpu::new(Box::new(system));
 
     if let Some(boot_rom_path) = matches.value_of("boot-rom") {
-      let rom = load_rom(boot_rom_path);
+      let rom = read_file(boot_rom_path);
       try_log!(cpu.system.load_bios(rom));
     } else {
       cpu.bootstrap();
     };
 
+    if let Ok(header) = CartridgeHeader::parse(&cart_rom) {
+      for warning in &header.warnings {
+        warn!("{}", warning);
+      }
+    }
     try_log!(cpu.system.load_cartridge(cart_rom));
 
+    if matches.value_of("rtc") == Some("emulated") {
+      cpu.system.set_rtc_mode(RtcMode::Emulated);
+    }
+
+    if let Some(image_path) = matches.value_of("camera") {
+      cpu.system.set_camera_source(try_log!(CameraSource::from_file(image_path)));
+    }
+
+    load_cheats(cart_path, &mut *cpu.system);
+
+    // Loaded after the rtc mode is set, so the clock only catches up
+    // with the time spent closed when following the wall clock.
+    let battery = BatterySave::new(cart_path);
+    if let Err(e) = battery.load(&mut *cpu.system) {
+      warn!("couldn't load save file: {}", e);
+    }
+
     if matches.is_present("debug") {
       // TODO: this doesn't work with the UI just yet.
-      debugger::run_debugger(cpu);
+      debugger::run_debugger(cpu, battery);
       exit(0);
+    } else if let Some(frames) = matches.value_of("frames") {
+      run_headless(cpu, battery, &matches, try_log!(frames.parse::<u64>()));
     } else {
-      run(cpu);
+      run(cpu, battery, &matches);
     }
   }
 }
 
-fn run(mut cpu: Cpu) {
+// F1-F4 toggle muting channels 1-4, F5-F8 toggle soloing them.
+fn toggle_audio_channel<S: SystemCtrl + ?Sized>(system: &mut S, keycode: Keycode) {
+  let (channel, solo) = match keycode {
+    Keycode::F1 => (0, false),
+    Keycode::F2 => (1, false),
+    Keycode::F3 => (2, false),
+    Keycode::F4 => (3, false),
+    Keycode::F5 => (0, true),
+    Keycode::F6 => (1, true),
+    Keycode::F7 => (2, true),
+    Keycode::F8 => (3, true),
+    _ => return,
+  };
+
+  if solo {
+    let solo = !system.audio_channel_solo(channel);
+    system.set_audio_channel_solo(channel, solo);
+    info!("audio channel {} solo {}", channel + 1, if solo { "on" } else { "off" });
+  } else {
+    let muted = !system.audio_channel_muted(channel);
+    system.set_audio_channel_muted(channel, muted);
+    info!("audio channel {} {}", channel + 1, if muted { "muted" } else { "unmuted" });
+  }
+}
+
+fn create_recorder<S>(system: &mut S, matches: &ArgMatches, sample_rate: u32) -> Option<Recorder>
+  where S: SystemCtrl + ?Sized
+{
+  matches.value_of("record-audio").map(|path| {
+    try_log!(Recorder::create(path, system, sample_rate, matches.is_present("record-stems")))
+  })
+}
+
+// Runs the emulator as fast as it can for a fixed amount of frames,
+// without a window or audio device. Used to record audio on CI.
+fn run_headless(mut cpu: Cpu, mut battery: BatterySave, matches: &ArgMatches, max_frames: u64) {
+  let sample_rate = if matches.is_present("record-audio") {
+    DEFAULT_SAMPLE_RATE
+  } else {
+    0
+  };
+  cpu.system.set_audio_sample_rate(sample_rate);
+
+  let mut recorder = create_recorder(&mut *cpu.system, matches, sample_rate);
+  let mut frames = 0u64;
+  let mut samples = vec![];
+  let mut frame_start = cpu.machine_cycles();
+
+  // Frames are counted in emulated time, since none are drawn while
+  // the LCD is off.
+  while frames < max_frames {
+    cpu.step();
+
+    if cpu.machine_cycles().wrapping_sub(frame_start) >= FRAME_CYCLES {
+      frame_start = frame_start.wrapping_add(FRAME_CYCLES);
+
+      samples.clear();
+      cpu.system.drain_audio_samples(&mut samples);
+      if let Some(ref mut recorder) = recorder {
+        try_log!(recorder.record(&samples, &mut *cpu.system));
+      }
+
+      if let Err(e) = battery.frame(&mut *cpu.system) {
+        warn!("couldn't write save file: {}", e);
+      }
+
+      frames += 1;
+    }
+  }
+
+  // try_log exits without running destructors, so the wav headers are
+  // finished before anything else can fail.
+  if let Some(ref mut recorder) = recorder {
+    try_log!(recorder.finish());
+  }
+  try_log!(battery.flush(&mut *cpu.system));
+}
+
+fn run(mut cpu: Cpu, mut battery: BatterySave, matches: &ArgMatches) {
   let scale = 4.0f64;
 
   let sdl_context = try_log!(sdl2::init());
@@ -122,6 +344,27 @@ fn run(mut cpu: Cpu) {
 
   let mut event_pump = try_log!(sdl_context.event_pump());
 
+  let record_path = matches.value_of("record-audio");
+
+  let (mut pacer, sample_rate) = if !matches.is_present("no-audio") {
+    let audio_subsystem = try_log!(sdl_context.audio());
+    let p = try_log!(AudioPacer::new(&audio_subsystem));
+    let rate = p.sample_rate();
+    (Pacer::Audio(p), rate)
+  } else if record_path.is_some() {
+    (Pacer::Clock(ClockPacer::new()), DEFAULT_SAMPLE_RATE)
+  } else {
+    (Pacer::Clock(ClockPacer::new()), 0)
+  };
+  cpu.system.set_audio_sample_rate(sample_rate);
+
+  let mut recorder = create_recorder(&mut *cpu.system, matches, sample_rate);
+
+  let mut rumble = Rumble::open();
+
+  let mut samples = vec![];
+  let mut frame_start = cpu.machine_cycles();
+
   let mut frame_count = 0;
   let mut start = Instant::now();
   'running: loop {
@@ -139,7 +382,7 @@ fn run(mut cpu: Cpu) {
             Keycode::RShift => cpu.system.set_button(Button::Select, true),
             Keycode::Space => cpu.system.set_button(Button::A, true),
             Keycode::LCtrl => cpu.system.set_button(Button::B, true),
-            _ => {}
+            _ => toggle_audio_channel(&mut *cpu.system, keycode),
           };
         }
         Event::KeyUp { keycode: Some(keycode), .. } => {
@@ -187,5 +430,31 @@ fn run(mut cpu: Cpu) {
       renderer.copy(&texture, None, Some(Rect::new(0, 0, size.0, size.1)));
       renderer.present();
     }
+
+    // Audio and pacing go by emulated time, since no frames are drawn
+    // while the LCD is off.
+    if cpu.machine_cycles().wrapping_sub(frame_start) >= FRAME_CYCLES {
+      frame_start = frame_start.wrapping_add(FRAME_CYCLES);
+
+      samples.clear();
+      cpu.system.drain_audio_samples(&mut samples);
+      if let Some(ref mut recorder) = recorder {
+        try_log!(recorder.record(&samples, &mut *cpu.system));
+      }
+      pacer.frame(&samples);
+
+      if let Some(ref mut rumble) = rumble {
+        rumble.set(cpu.system.rumble_active());
+      }
+
+      if let Err(e) = battery.frame(&mut *cpu.system) {
+        warn!("couldn't write save file: {}", e);
+      }
+    }
+  }
+
+  if let Some(ref mut recorder) = recorder {
+    try_log!(recorder.finish());
   }
+  try_log!(battery.flush(&mut *cpu.system));
 }
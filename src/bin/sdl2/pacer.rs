@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sdl2::AudioSubsystem;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+
+// 70224 clock cycles per frame at 4194304hz (~59.73 fps).
+const FRAME_NANOS: u32 = 16742706;
+
+// The same frame in machine cycles, which is how often the emulator
+// should call `Pacer::frame` whether or not the LCD is on.
+pub const FRAME_CYCLES: u32 = 17556;
+
+// How much audio we let the buffer hold before waiting on it.
+const AUDIO_LATENCY_FRAMES: u32 = 3;
+
+// If we fall this far behind we stop trying to catch up.
+const MAX_LAG_MILLIS: u64 = 100;
+
+// If the audio device doesn't take any samples for this long we pace
+// with the clock until it does.
+const MAX_STALL_MILLIS: u64 = 250;
+
+// Keeps the emulator running at the speed of a real gameboy. When
+// audio is enabled the emulator waits on the audio buffer to drain,
+// otherwise it sleeps until the next frame is due.
+pub enum Pacer {
+  Audio(AudioPacer),
+  Clock(ClockPacer),
+}
+
+impl Pacer {
+  // Called once every `FRAME_CYCLES` with the audio samples generated
+  // during that time.
+  pub fn frame(&mut self, samples: &[(i16, i16)]) {
+    match *self {
+      Pacer::Audio(ref mut p) => p.frame(samples),
+      Pacer::Clock(ref mut p) => p.frame(),
+    }
+  }
+}
+
+// Interleaved stereo samples shared between the emulator and the
+// audio callback.
+type SampleBuffer = Arc<Mutex<VecDeque<i16>>>;
+
+struct Playback {
+  buffer: SampleBuffer,
+}
+
+impl AudioCallback for Playback {
+  type Channel = i16;
+
+  // Runs on the audio thread. Plays silence if the emulator can't
+  // keep up.
+  fn callback(&mut self, out: &mut [i16]) {
+    let mut buffer = self.buffer.lock().unwrap();
+    for x in out.iter_mut() {
+      *x = buffer.pop_front().unwrap_or(0);
+    }
+  }
+}
+
+pub struct AudioPacer {
+  // Playback stops when the device is dropped.
+  _device: AudioDevice<Playback>,
+  buffer: SampleBuffer,
+  sample_rate: u32,
+  max_buffered: usize,
+  // Set when the device stopped pulling samples.
+  stalled: bool,
+  clock: ClockPacer,
+}
+
+impl AudioPacer {
+  // Opens the audio device. The sample rate the device ended up with
+  // is available through `sample_rate`.
+  pub fn new(audio: &AudioSubsystem) -> Result<AudioPacer, String> {
+    let desired = AudioSpecDesired {
+      freq: Some(44100),
+      channels: Some(2),
+      samples: Some(1024),
+    };
+    let buffer = Arc::new(Mutex::new(VecDeque::new()));
+
+    let mut rate = 0;
+    let device = try!(audio.open_playback(None, &desired, |spec| {
+      rate = spec.freq as u32;
+      Playback { buffer: buffer.clone() }
+    }));
+
+    // samples per second * frame time * frames * channels
+    let max_buffered = (rate as u64 * FRAME_NANOS as u64 * AUDIO_LATENCY_FRAMES as u64 /
+                        1_000_000_000) as usize * 2;
+
+    device.resume();
+
+    Ok(AudioPacer {
+      _device: device,
+      buffer: buffer,
+      sample_rate: rate,
+      max_buffered: max_buffered,
+      stalled: false,
+      clock: ClockPacer::new(),
+    })
+  }
+
+  pub fn sample_rate(&self) -> u32 {
+    self.sample_rate
+  }
+
+  fn frame(&mut self, samples: &[(i16, i16)]) {
+    {
+      let mut buffer = self.buffer.lock().unwrap();
+      for &(left, right) in samples {
+        buffer.push_back(left);
+        buffer.push_back(right);
+      }
+
+      if self.stalled {
+        if buffer.len() <= self.max_buffered {
+          self.stalled = false;
+        } else {
+          // Drop the oldest samples so the buffer doesn't keep growing.
+          let excess = buffer.len() - self.max_buffered;
+          buffer.drain(..excess);
+        }
+      }
+    }
+
+    if self.stalled {
+      self.clock.frame();
+      return;
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(MAX_STALL_MILLIS);
+    while self.buffer.lock().unwrap().len() > self.max_buffered {
+      if Instant::now() >= deadline {
+        warn!("audio device stopped playing, pacing with the clock");
+        self.stalled = true;
+        self.clock = ClockPacer::new();
+        return;
+      }
+      thread::sleep(Duration::from_millis(1));
+    }
+  }
+}
+
+pub struct ClockPacer {
+  next_frame: Instant,
+}
+
+impl ClockPacer {
+  pub fn new() -> ClockPacer {
+    ClockPacer { next_frame: Instant::now() }
+  }
+
+  fn frame(&mut self) {
+    self.next_frame += Duration::new(0, FRAME_NANOS);
+
+    let now = Instant::now();
+    if self.next_frame > now {
+      thread::sleep(self.next_frame - now);
+    } else if now - self.next_frame > Duration::from_millis(MAX_LAG_MILLIS) {
+      self.next_frame = now;
+    }
+  }
+}
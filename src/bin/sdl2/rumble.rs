@@ -0,0 +1,69 @@
+use sdl2_sys::haptic as ffi;
+use sdl2_sys::sdl::{SDL_InitSubSystem, SDL_QuitSubSystem, SDL_INIT_HAPTIC};
+
+// SDL_HAPTIC_INFINITY, plays the effect until it's stopped.
+const RUMBLE_FOREVER: u32 = 4294967295;
+
+// Forwards the cartridge's rumble motor to the first haptic device
+// SDL can find. rust-sdl2 doesn't wrap the haptic api, so this talks
+// to SDL directly.
+pub struct Rumble {
+  haptic: *mut ffi::SDL_Haptic,
+  active: bool,
+}
+
+impl Rumble {
+  // Returns None if there is no haptic device that supports rumble.
+  pub fn open() -> Option<Rumble> {
+    unsafe {
+      if SDL_InitSubSystem(SDL_INIT_HAPTIC) != 0 {
+        return None;
+      }
+      if ffi::SDL_NumHaptics() < 1 {
+        SDL_QuitSubSystem(SDL_INIT_HAPTIC);
+        return None;
+      }
+
+      let haptic = ffi::SDL_HapticOpen(0);
+      if haptic.is_null() {
+        SDL_QuitSubSystem(SDL_INIT_HAPTIC);
+        return None;
+      }
+      if ffi::SDL_HapticRumbleInit(haptic) != 0 {
+        ffi::SDL_HapticClose(haptic);
+        SDL_QuitSubSystem(SDL_INIT_HAPTIC);
+        return None;
+      }
+
+      Some(Rumble {
+        haptic: haptic,
+        active: false,
+      })
+    }
+  }
+
+  // Called once a frame with the state of the motor.
+  pub fn set(&mut self, active: bool) {
+    if active == self.active {
+      return;
+    }
+    unsafe {
+      if active {
+        ffi::SDL_HapticRumblePlay(self.haptic, 1.0, RUMBLE_FOREVER);
+      } else {
+        ffi::SDL_HapticRumbleStop(self.haptic);
+      }
+    }
+    self.active = active;
+  }
+}
+
+impl Drop for Rumble {
+  fn drop(&mut self) {
+    unsafe {
+      ffi::SDL_HapticRumbleStop(self.haptic);
+      ffi::SDL_HapticClose(self.haptic);
+      SDL_QuitSubSystem(SDL_INIT_HAPTIC);
+    }
+  }
+}
@@ -1,22 +1,53 @@
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
-use libc::{int8_t, uint8_t, c_char, c_void};
+use libc::{int8_t, int16_t, uint8_t, uint32_t, size_t, c_char, c_void};
 use std::thread;
 use std::ffi::CStr;
 use std::ptr;
 use std::cmp;
 use std::result;
+use std::str;
 
+use super::audio::DEFAULT_SAMPLE_RATE;
+use super::cartridge::CartridgeError;
 use super::cpu::Cpu;
 use super::system;
 use super::gamepad::Button;
 use super::debugger::Debugger;
+use super::loader::load_rom;
 
 const MAX_ERROR_SIZE: usize = 1024;
 
+// Error codes returned in `CApiError.code`, 0 means no error.
 enum CApiErrors {
   Generic = 1,
+  // Cartridge errors, see `CartridgeError`.
+  BadRomSize = 2,
+  UnknownCartType = 3,
+  UnsupportedMapper = 4,
+  UnknownRamSize = 5,
+  ChecksumMismatch = 6,
+}
+
+// Errors that have a more specific code than `Generic`.
+trait ApiError: ToString {
+  fn code(&self) -> CApiErrors {
+    CApiErrors::Generic
+  }
+}
+
+impl ApiError for String {}
+
+impl ApiError for str::Utf8Error {}
+
+impl ApiError for CartridgeError {
+  fn code(&self) -> CApiErrors {
+    match *self {
+      CartridgeError::BadSize(_) => CApiErrors::BadRomSize,
+      CartridgeError::UnknownType(_) => CApiErrors::UnknownCartType,
+      CartridgeError::UnsupportedMapper(_) => CApiErrors::UnsupportedMapper,
+      CartridgeError::UnknownRamSize(_) => CApiErrors::UnknownRamSize,
+      CartridgeError::ChecksumMismatch { .. } => CApiErrors::ChecksumMismatch,
+    }
+  }
 }
 
 macro_rules! try_api {
@@ -32,10 +63,13 @@ macro_rules! try_api {
         assert!(!$err.is_null());
         &mut *$err
       };
-      e.code = CApiErrors::Generic as uint8_t;
+      e.code = ApiError::code(&err) as uint8_t;
       let s = err.to_string();
       let p = s.as_ptr() as *mut u8;
-      ptr::copy_nonoverlapping(p, e.error, cmp::max(s.len(), MAX_ERROR_SIZE));
+      // The buffer is MAX_ERROR_SIZE bytes, leave room for the NUL.
+      let len = cmp::min(s.len(), MAX_ERROR_SIZE - 1);
+      ptr::copy_nonoverlapping(p, e.error, len);
+      *e.error.offset(len as isize) = 0;
       //return mem::transmute($err_ret as *const u64);
       $err_ret;
     }
@@ -52,19 +86,8 @@ pub struct CApiError {
 #[derive(Debug)]
 pub struct CApiGameboy {
   cpu: Cpu,
-}
-
-fn load_rom<P: AsRef<Path>>(path: P) -> Result<Box<[u8]>, String> {
-  let mut file = match File::open(path) {
-    Ok(f) => f,
-    Err(e) => return Err(format!("{}", e)),
-  };
-  let mut file_buf = Vec::new();
-  match file.read_to_end(&mut file_buf) {
-    Ok(_) => (),
-    Err(e) => return Err(format!("{}", e)),
-  };
-  Ok(file_buf.into_boxed_slice())
+  audio: Vec<(i16, i16)>,
+  audio_sample_rate: u32,
 }
 
 #[no_mangle]
@@ -73,7 +96,11 @@ pub unsafe extern "C" fn gb_new() -> *mut CApiGameboy {
   let system = system::System::new();
   let cpu = Cpu::new(Box::new(system));
 
-  Box::into_raw(Box::new(CApiGameboy { cpu: cpu }))
+  Box::into_raw(Box::new(CApiGameboy {
+    cpu: cpu,
+    audio: vec![],
+    audio_sample_rate: DEFAULT_SAMPLE_RATE,
+  }))
 }
 
 #[no_mangle]
@@ -124,6 +151,60 @@ pub unsafe extern "C" fn gb_updated_frame(gb: *mut CApiGameboy, dst: *mut uint8_
   0
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn gb_set_audio_sample_rate(gb: *mut CApiGameboy, rate: uint32_t) {
+  let mut gb = {
+    assert!(!gb.is_null());
+    &mut *gb
+  };
+
+  gb.cpu.system.set_audio_sample_rate(rate);
+  gb.audio_sample_rate = rate;
+}
+
+// Returns 1 while the cartridge's rumble motor is on.
+#[no_mangle]
+pub unsafe extern "C" fn gb_rumble_active(gb: *mut CApiGameboy) -> int8_t {
+  let gb = {
+    assert!(!gb.is_null());
+    &mut *gb
+  };
+
+  gb.cpu.system.rumble_active() as int8_t
+}
+
+// Copies up to `len` interleaved stereo samples (left, right) into dst
+// and returns the number of samples copied. Samples that don't fit are
+// kept for the next call, up to a second worth of audio.
+#[no_mangle]
+pub unsafe extern "C" fn gb_audio_samples(gb: *mut CApiGameboy,
+                                          dst: *mut int16_t,
+                                          len: size_t)
+                                          -> size_t {
+  let mut gb = {
+    assert!(!gb.is_null());
+    &mut *gb
+  };
+
+  gb.cpu.system.drain_audio_samples(&mut gb.audio);
+
+  // Drop the oldest samples in case nobody is keeping up.
+  let max_len = gb.audio_sample_rate as usize;
+  if gb.audio.len() > max_len {
+    let excess = gb.audio.len() - max_len;
+    gb.audio.drain(..excess);
+  }
+
+  let n = cmp::min(gb.audio.len(), len);
+  for (i, &(left, right)) in gb.audio[..n].iter().enumerate() {
+    *dst.offset(i as isize * 2) = left;
+    *dst.offset(i as isize * 2 + 1) = right;
+  }
+  gb.audio.drain(..n);
+
+  n
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn gb_set_button(gb: *mut CApiGameboy, btn: uint8_t, pressed: bool) {
   let mut gb = {
@@ -0,0 +1,315 @@
+use std::cmp;
+use std::path::Path;
+
+use super::super::image::{self, GrayImage};
+
+// Size of the pictures the camera takes.
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+// The picture is stored as 16x14 tiles in ram bank 0.
+const IMAGE_START: usize = 0x0100;
+
+// The sensor registers, mapped to 0xA000-0xA035 (and mirrored every
+// 0x80 bytes) when ram bank 0x10 is selected. Only A000 can be read.
+//
+// A000  Bit 0 starts a capture and reads 1 until it's done
+// A001  Bits 0-4 gain, bits 5-6 edge enhancement mode, bit 7 N
+// A002  Exposure time, upper byte
+// A003  Exposure time, lower byte
+// A004  Bits 4-6 edge enhancement ratio, bits 0-3 output voltage
+// A005  Zero point calibration and output offset
+// A006-A035  4x4 dithering/contrast matrix, 3 thresholds per pixel
+const REGISTERS: usize = 0x36;
+const CAPTURE: usize = 0x00;
+const GAIN_EDGE: usize = 0x01;
+const EXPOSURE_HI: usize = 0x02;
+const EXPOSURE_LO: usize = 0x03;
+const EDGE_RATIO: usize = 0x04;
+const MATRIX_START: usize = 0x06;
+
+// Edge enhancement ratios selected by A004.
+const EDGE_RATIOS: [f64; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+// What a frame looks like without a source: plain mid gray.
+const NO_SOURCE_GRAY: u8 = 0x80;
+
+// Where the sensor gets its frames from, instead of a real camera.
+// Only made through the constructors, so still images always have the
+// camera's resolution.
+pub struct CameraSource(Source);
+
+enum Source {
+  // A still image, CAMERA_WIDTH x CAMERA_HEIGHT grayscale.
+  Image(Vec<u8>),
+  // Called for every capture to fill a CAMERA_WIDTH x CAMERA_HEIGHT
+  // grayscale buffer, 0 is black and 255 white.
+  Callback(Box<FnMut(&mut [u8]) + Send>),
+}
+
+impl CameraSource {
+  // A still image of any size, scaled to the camera's resolution.
+  pub fn from_image(image: &GrayImage) -> Result<CameraSource, String> {
+    if image.width == 0 || image.height == 0 || image.pixels.len() != image.width * image.height {
+      return Err(format!("camera: invalid {}x{} image with {} pixels",
+                         image.width,
+                         image.height,
+                         image.pixels.len()));
+    }
+    Ok(CameraSource(Source::Image(image.resize(CAMERA_WIDTH, CAMERA_HEIGHT).pixels)))
+  }
+
+  // A still image loaded from a png or pgm file.
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<CameraSource, String> {
+    let image = try!(image::load_image(path));
+    CameraSource::from_image(&image)
+  }
+
+  pub fn from_callback<F>(func: F) -> CameraSource
+    where F: FnMut(&mut [u8]) + Send + 'static
+  {
+    CameraSource(Source::Callback(Box::new(func)))
+  }
+}
+
+// Mitsubishi M64282FP image sensor of the Game Boy Camera.
+pub struct Camera {
+  registers: [u8; REGISTERS],
+  // Steps until the capture in progress is done.
+  capture_steps: u32,
+  source: Option<CameraSource>,
+}
+
+impl Camera {
+  pub fn new() -> Camera {
+    Camera {
+      registers: [0; REGISTERS],
+      capture_steps: 0,
+      source: None,
+    }
+  }
+
+  pub fn set_source(&mut self, source: CameraSource) {
+    self.source = Some(source);
+  }
+
+  pub fn read(&self, addr: u16) -> u8 {
+    match (addr & 0x7F) as usize {
+      CAPTURE => self.registers[CAPTURE],
+      _ => 0x00,
+    }
+  }
+
+  pub fn write(&mut self, addr: u16, value: u8) {
+    let reg = (addr & 0x7F) as usize;
+    if reg >= REGISTERS {
+      return;
+    }
+    if reg != CAPTURE {
+      self.registers[reg] = value;
+      return;
+    }
+
+    let value = value & 0x07;
+    if value & 0x01 != 0 && self.capture_steps == 0 {
+      self.capture_steps = self.capture_len();
+    } else if value & 0x01 == 0 {
+      self.capture_steps = 0;
+    }
+    self.registers[CAPTURE] = value;
+  }
+
+  fn exposure(&self) -> u32 {
+    (self.registers[EXPOSURE_HI] as u32) << 8 | self.registers[EXPOSURE_LO] as u32
+  }
+
+  // How many steps a capture takes. Mostly the exposure time, plus a
+  // little extra when N is clear.
+  fn capture_len(&self) -> u32 {
+    let extra = if self.registers[GAIN_EDGE] & 0x80 != 0 { 0 } else { 2048 };
+    (129792 + extra + self.exposure() * 64) / 4
+  }
+
+  // Called for every system step. Returns true when a capture finished
+  // and the picture was written to the ram.
+  pub fn step(&mut self, ram: &mut [u8]) -> bool {
+    if self.capture_steps == 0 {
+      return false;
+    }
+    self.capture_steps -= 1;
+    if self.capture_steps > 0 {
+      return false;
+    }
+
+    self.capture(ram);
+    self.registers[CAPTURE] &= !0x01;
+    true
+  }
+
+  fn frame(&mut self) -> Vec<u8> {
+    let mut frame = vec![NO_SOURCE_GRAY; CAMERA_WIDTH * CAMERA_HEIGHT];
+    match self.source {
+      Some(CameraSource(Source::Image(ref pixels))) => frame.copy_from_slice(pixels),
+      Some(CameraSource(Source::Callback(ref mut func))) => func(&mut frame),
+      None => (),
+    }
+    frame
+  }
+
+  // Brightness scales with the exposure time, where 0x1000 leaves the
+  // frame as it is, and every gain step adds about 1.5dB.
+  fn expose(&self, frame: &[u8]) -> Vec<f64> {
+    let gain = 10f64.powf((self.registers[GAIN_EDGE] & 0x1F) as f64 * 1.5 / 20.0);
+    let scale = self.exposure() as f64 / 0x1000 as f64 * gain;
+    frame.iter().map(|&v| v as f64 * scale).collect()
+  }
+
+  // Edge enhancement, horizontally (mode 1), vertically (mode 2) or in
+  // both directions (mode 3).
+  fn enhance_edges(&self, pixels: &[f64]) -> Vec<f64> {
+    let mode = (self.registers[GAIN_EDGE] >> 5) & 0x03;
+    if mode == 0 {
+      return pixels.to_vec();
+    }
+    let ratio = EDGE_RATIOS[((self.registers[EDGE_RATIO] >> 4) & 0x07) as usize];
+
+    let at = |x: usize, y: usize| pixels[y * CAMERA_WIDTH + x];
+    let mut out = Vec::with_capacity(pixels.len());
+    for y in 0..CAMERA_HEIGHT {
+      for x in 0..CAMERA_WIDTH {
+        // Neighbours past the edges are the edge pixels themselves.
+        let (left, right) = (x.saturating_sub(1), cmp::min(x + 1, CAMERA_WIDTH - 1));
+        let (up, down) = (y.saturating_sub(1), cmp::min(y + 1, CAMERA_HEIGHT - 1));
+
+        let v = at(x, y);
+        let mut edge = 0.0;
+        if mode & 0x01 != 0 {
+          edge += 2.0 * v - at(left, y) - at(right, y);
+        }
+        if mode & 0x02 != 0 {
+          edge += 2.0 * v - at(x, up) - at(x, down);
+        }
+        out.push(v + edge * ratio);
+      }
+    }
+    out
+  }
+
+  // Takes a picture and stores it in the ram as 2bpp tiles. The
+  // matrix registers hold 3 thresholds for every pixel of a 4x4 block,
+  // which both dither the picture and set its contrast.
+  fn capture(&mut self, ram: &mut [u8]) {
+    let frame = self.frame();
+    let pixels = self.enhance_edges(&self.expose(&frame));
+
+    for y in 0..CAMERA_HEIGHT {
+      for x in 0..CAMERA_WIDTH {
+        let v = pixels[y * CAMERA_WIDTH + x];
+        let thresholds = MATRIX_START + ((x & 3) + (y & 3) * 4) * 3;
+        let color = if v < self.registers[thresholds] as f64 {
+          3
+        } else if v < self.registers[thresholds + 1] as f64 {
+          2
+        } else if v < self.registers[thresholds + 2] as f64 {
+          1
+        } else {
+          0
+        };
+
+        let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+        let loc = IMAGE_START + tile * 16 + (y % 8) * 2;
+        if loc + 1 >= ram.len() {
+          return;
+        }
+        let bit = 0x80 >> (x % 8);
+        ram[loc] &= !bit;
+        ram[loc + 1] &= !bit;
+        if color & 0x01 != 0 {
+          ram[loc] |= bit;
+        }
+        if color & 0x02 != 0 {
+          ram[loc + 1] |= bit;
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  // Thresholds that map 0x00-0x3F to black, 0x40-0x7F to dark gray and
+  // so on, the same for every pixel.
+  fn set_matrix(camera: &mut Camera) {
+    for i in 0..16 {
+      camera.write(0xA000 + (MATRIX_START + i * 3) as u16, 0x40);
+      camera.write(0xA000 + (MATRIX_START + i * 3 + 1) as u16, 0x80);
+      camera.write(0xA000 + (MATRIX_START + i * 3 + 2) as u16, 0xC0);
+    }
+  }
+
+  #[test]
+  fn test_capture() {
+    let mut camera = Camera::new();
+    // A gradient from black on the left to white on the right.
+    let mut image = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+    for (i, v) in image.iter_mut().enumerate() {
+      *v = ((i % CAMERA_WIDTH) * 2) as u8;
+    }
+    let image = GrayImage {
+      width: CAMERA_WIDTH,
+      height: CAMERA_HEIGHT,
+      pixels: image,
+    };
+    camera.set_source(CameraSource::from_image(&image).unwrap());
+    set_matrix(&mut camera);
+    camera.write(0xA002, 0x10);
+    camera.write(0xA003, 0x00);
+
+    let mut ram = vec![0; 0x2000];
+    camera.write(0xA000, 0x01);
+    assert_eq!(camera.read(0xA000) & 0x01, 0x01);
+    let mut steps = 0;
+    while !camera.step(&mut ram) {
+      steps += 1;
+    }
+    assert_eq!(steps + 1, camera.capture_len());
+    assert_eq!(camera.read(0xA000) & 0x01, 0x00);
+
+    // First tile row: black, then dark gray (only the high bit plane
+    // set), light gray and white.
+    assert_eq!(&ram[IMAGE_START..IMAGE_START + 2], &[0xFF, 0xFF]);
+    assert_eq!(&ram[IMAGE_START + 16 * 4..IMAGE_START + 16 * 4 + 2], &[0x00, 0xFF]);
+    assert_eq!(&ram[IMAGE_START + 16 * 8..IMAGE_START + 16 * 8 + 2], &[0xFF, 0x00]);
+    assert_eq!(&ram[IMAGE_START + 16 * 15..IMAGE_START + 16 * 15 + 2], &[0x00, 0x00]);
+  }
+
+  #[test]
+  fn test_invalid_image() {
+    let image = GrayImage {
+      width: CAMERA_WIDTH,
+      height: CAMERA_HEIGHT,
+      pixels: vec![0; 16],
+    };
+    assert!(CameraSource::from_image(&image).is_err());
+  }
+
+  #[test]
+  fn test_callback() {
+    let mut camera = Camera::new();
+    camera.set_source(CameraSource::from_callback(|frame| {
+      for v in frame.iter_mut() {
+        *v = 0xFF;
+      }
+    }));
+    set_matrix(&mut camera);
+    camera.write(0xA002, 0x10);
+
+    let mut ram = vec![0xAA; 0x2000];
+    camera.write(0xA000, 0x01);
+    while !camera.step(&mut ram) {}
+    assert!(ram[IMAGE_START..IMAGE_START + 0x0E00].iter().all(|&b| b == 0));
+    assert_eq!(ram[IMAGE_START + 0x0E00], 0xAA);
+  }
+}
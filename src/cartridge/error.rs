@@ -0,0 +1,38 @@
+use std::fmt;
+
+use super::CartType;
+
+// Why a rom couldn't be loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CartridgeError {
+  // The rom is too small to have a header.
+  BadSize(usize),
+  UnknownType(u8),
+  // A known cartridge type whose mapper isn't emulated.
+  UnsupportedMapper(CartType),
+  UnknownRamSize(u8),
+  // The boot rom locks up on a bad header checksum, so real hardware
+  // wouldn't run the rom either.
+  ChecksumMismatch { expected: u8, actual: u8 },
+}
+
+impl fmt::Display for CartridgeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      CartridgeError::BadSize(len) => {
+        write!(f, "invalid cartridge: too small to have a header ({} bytes)", len)
+      }
+      CartridgeError::UnknownType(v) => write!(f, "unknown cartridge type: {:#04x}", v),
+      CartridgeError::UnsupportedMapper(cart_type) => {
+        write!(f, "unsupported mapper: {}", cart_type.mapper_name())
+      }
+      CartridgeError::UnknownRamSize(v) => write!(f, "unknown ram size: {:#04x}", v),
+      CartridgeError::ChecksumMismatch { expected, actual } => {
+        write!(f,
+               "header checksum mismatch: header says {:#04x}, computed {:#04x}",
+               expected,
+               actual)
+      }
+    }
+  }
+}
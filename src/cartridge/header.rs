@@ -0,0 +1,362 @@
+use std::fmt;
+
+use num::FromPrimitive;
+
+use super::{CartType, CartridgeError};
+use super::ram::RamSize;
+use super::rom::RomSize;
+
+// The header lives at 0x0100-0x014F.
+pub const HEADER_END: usize = 0x0150;
+
+const LOGO_START: usize = 0x0104;
+const MMM01_MENU_SIZE: usize = 0x8000;
+pub const LOGO: [u8; 48] = [0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83,
+                            0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
+                            0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63,
+                            0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E];
+
+// An old licensee code of 0x33 means the new licensee code, the
+// manufacturer code and the shorter title are used.
+const USE_NEW_LICENSEE: u8 = 0x33;
+
+// Problems found in a header. Real hardware only checks the logo and
+// the header checksum, plenty of homebrew and bootleg roms get the
+// rest wrong and still run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderWarning {
+  BadLogo,
+  HeaderChecksumMismatch { expected: u8, actual: u8 },
+  GlobalChecksumMismatch { expected: u16, actual: u16 },
+  UnknownCartType(u8),
+  UnknownRomSize(u8),
+  UnknownRamSize(u8),
+  // The rom is padded or mirrored to a power of two when it is loaded.
+  RomSizeMismatch { expected: usize, actual: usize },
+}
+
+impl fmt::Display for HeaderWarning {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      HeaderWarning::BadLogo => write!(f, "nintendo logo doesn't match"),
+      HeaderWarning::HeaderChecksumMismatch { expected, actual } => {
+        write!(f,
+               "header checksum mismatch: header says {:#04x}, computed {:#04x}",
+               expected,
+               actual)
+      }
+      HeaderWarning::GlobalChecksumMismatch { expected, actual } => {
+        write!(f,
+               "global checksum mismatch: header says {:#06x}, computed {:#06x}",
+               expected,
+               actual)
+      }
+      HeaderWarning::UnknownCartType(v) => write!(f, "unknown cartridge type: {:#04x}", v),
+      HeaderWarning::UnknownRomSize(v) => write!(f, "unknown rom size: {:#04x}", v),
+      HeaderWarning::UnknownRamSize(v) => write!(f, "unknown ram size: {:#04x}", v),
+      HeaderWarning::RomSizeMismatch { expected, actual } => {
+        write!(f,
+               "rom size mismatch: header says {} bytes, got {}",
+               expected,
+               actual)
+      }
+    }
+  }
+}
+
+// 0x0143
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CgbFlag {
+  DmgOnly,
+  // 0x80, works on both
+  CgbSupported,
+  // 0xC0
+  CgbOnly,
+}
+
+// 0x014A
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Destination {
+  Japanese,
+  Overseas,
+  Unknown(u8),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CartridgeHeader {
+  pub title: String,
+  // Only on newer cartridges.
+  pub manufacturer_code: Option<String>,
+  pub cgb_flag: CgbFlag,
+  // Only used when the old licensee code is 0x33.
+  pub new_licensee_code: Option<String>,
+  pub old_licensee_code: u8,
+  pub sgb_flag: bool,
+  // The raw values, see `cart_type`, `rom_size` and `ram_size`.
+  pub cart_type: u8,
+  pub rom_size: u8,
+  pub ram_size: u8,
+  pub destination: Destination,
+  pub version: u8,
+  pub header_checksum: u8,
+  pub global_checksum: u16,
+  pub warnings: Vec<HeaderWarning>,
+}
+
+fn ascii(data: &[u8]) -> String {
+  let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+  String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+// Checksum over 0x0134-0x014C that the boot rom verifies.
+pub fn header_checksum(data: &[u8]) -> u8 {
+  data[0x0134..0x014D].iter().fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1))
+}
+
+// Sum of every byte in the rom except the global checksum itself.
+pub fn global_checksum(data: &[u8]) -> u16 {
+  data.iter()
+    .enumerate()
+    .filter(|&(i, _)| i != 0x014E && i != 0x014F)
+    .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16))
+}
+
+impl CartridgeHeader {
+  // Parses the header of a rom. Only fails if the rom is too small to
+  // have a header, anything else ends up in `warnings`. Whether the rom
+  // can be loaded is up to `Cartridge::load`.
+  //
+  // MMM01 multicarts start with the first game, the header describing
+  // the cartridge is the one of the menu in the last 32KB.
+  pub fn parse(data: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+    let header = try!(CartridgeHeader::parse_at(data, 0));
+    if data.len() > MMM01_MENU_SIZE {
+      if let Ok(menu) = CartridgeHeader::parse_at(data, data.len() - MMM01_MENU_SIZE) {
+        if menu.is_mmm01() && !header.is_mmm01() {
+          return Ok(menu);
+        }
+      }
+    }
+    Ok(header)
+  }
+
+  // Parses a header that isn't at the start of the rom, like the one of
+  // the MMM01 menu in the last 32KB. The global checksum and the rom
+  // size are still checked against the whole rom.
+  pub fn parse_at(rom: &[u8], offset: usize) -> Result<CartridgeHeader, CartridgeError> {
+    if rom.len() < offset + HEADER_END {
+      return Err(CartridgeError::BadSize(rom.len()));
+    }
+    let data = &rom[offset..];
+
+    let old_licensee_code = data[0x014B];
+    let new_layout = old_licensee_code == USE_NEW_LICENSEE;
+
+    let (title, manufacturer_code) = if new_layout {
+      (ascii(&data[0x0134..0x013F]), Some(ascii(&data[0x013F..0x0143])))
+    } else {
+      (ascii(&data[0x0134..0x0144]), None)
+    };
+
+    let cgb_flag = match data[0x0143] {
+      0xC0 => CgbFlag::CgbOnly,
+      v if v & 0x80 != 0 => CgbFlag::CgbSupported,
+      _ => CgbFlag::DmgOnly,
+    };
+
+    let destination = match data[0x014A] {
+      0x00 => Destination::Japanese,
+      0x01 => Destination::Overseas,
+      v => Destination::Unknown(v),
+    };
+
+    let mut header = CartridgeHeader {
+      title: title,
+      manufacturer_code: manufacturer_code,
+      cgb_flag: cgb_flag,
+      new_licensee_code: if new_layout {
+        Some(ascii(&data[0x0144..0x0146]))
+      } else {
+        None
+      },
+      old_licensee_code: old_licensee_code,
+      sgb_flag: data[0x0146] == 0x03,
+      cart_type: data[0x0147],
+      rom_size: data[0x0148],
+      ram_size: data[0x0149],
+      destination: destination,
+      version: data[0x014C],
+      header_checksum: data[0x014D],
+      global_checksum: (data[0x014E] as u16) << 8 | data[0x014F] as u16,
+      warnings: vec![],
+    };
+    header.validate(data, rom);
+
+    Ok(header)
+  }
+
+  fn validate(&mut self, data: &[u8], rom: &[u8]) {
+    if data[LOGO_START..LOGO_START + LOGO.len()] != LOGO[..] {
+      self.warnings.push(HeaderWarning::BadLogo);
+    }
+
+    let actual = header_checksum(data);
+    if actual != self.header_checksum {
+      self.warnings.push(HeaderWarning::HeaderChecksumMismatch {
+        expected: self.header_checksum,
+        actual: actual,
+      });
+    }
+
+    let actual = global_checksum(rom);
+    if actual != self.global_checksum {
+      self.warnings.push(HeaderWarning::GlobalChecksumMismatch {
+        expected: self.global_checksum,
+        actual: actual,
+      });
+    }
+
+    if self.cart_type().is_none() {
+      self.warnings.push(HeaderWarning::UnknownCartType(self.cart_type));
+    }
+    match self.rom_size() {
+      Some(size) => {
+        if size.as_usize() != rom.len() {
+          self.warnings.push(HeaderWarning::RomSizeMismatch {
+            expected: size.as_usize(),
+            actual: rom.len(),
+          });
+        }
+      }
+      None => self.warnings.push(HeaderWarning::UnknownRomSize(self.rom_size)),
+    }
+    if self.ram_size().is_none() {
+      self.warnings.push(HeaderWarning::UnknownRamSize(self.ram_size));
+    }
+  }
+
+  pub fn cart_type(&self) -> Option<CartType> {
+    FromPrimitive::from_u8(self.cart_type)
+  }
+
+  fn is_mmm01(&self) -> bool {
+    match self.cart_type() {
+      Some(CartType::Mmm01) | Some(CartType::Mmm01Ram) | Some(CartType::Mmm01RamBattery) => true,
+      _ => false,
+    }
+  }
+
+  pub fn rom_size(&self) -> Option<RomSize> {
+    FromPrimitive::from_u8(self.rom_size)
+  }
+
+  pub fn ram_size(&self) -> Option<RamSize> {
+    FromPrimitive::from_u8(self.ram_size)
+  }
+
+  pub fn logo_valid(&self) -> bool {
+    !self.warnings.contains(&HeaderWarning::BadLogo)
+  }
+
+  pub fn header_checksum_valid(&self) -> bool {
+    !self.warnings.iter().any(|w| match *w {
+      HeaderWarning::HeaderChecksumMismatch { .. } => true,
+      _ => false,
+    })
+  }
+
+  // For callers that refuse roms the boot rom wouldn't start.
+  pub fn check_header_checksum(&self) -> Result<(), CartridgeError> {
+    for warning in &self.warnings {
+      if let HeaderWarning::HeaderChecksumMismatch { expected, actual } = *warning {
+        return Err(CartridgeError::ChecksumMismatch {
+          expected: expected,
+          actual: actual,
+        });
+      }
+    }
+    Ok(())
+  }
+
+  pub fn global_checksum_valid(&self) -> bool {
+    !self.warnings.iter().any(|w| match *w {
+      HeaderWarning::GlobalChecksumMismatch { .. } => true,
+      _ => false,
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::LOGO;
+
+  fn test_rom() -> Vec<u8> {
+    let mut data = vec![0; 0x8000];
+    data[0x0104..0x0134].copy_from_slice(&LOGO);
+    data[0x0134..0x013F].copy_from_slice(b"TESTGAME\0\0\0");
+    data[0x013F..0x0143].copy_from_slice(b"ABCD");
+    data[0x0143] = 0x80;
+    data[0x0144..0x0146].copy_from_slice(b"01");
+    data[0x0146] = 0x03;
+    data[0x0147] = 0x1B;
+    data[0x0149] = 0x03;
+    data[0x014A] = 0x01;
+    data[0x014B] = 0x33;
+    data[0x014C] = 0x02;
+    data[0x014D] = header_checksum(&data);
+    let sum = global_checksum(&data);
+    data[0x014E] = (sum >> 8) as u8;
+    data[0x014F] = sum as u8;
+    data
+  }
+
+  #[test]
+  fn test_parse() {
+    let data = test_rom();
+    let header = CartridgeHeader::parse(&data).unwrap();
+    assert_eq!(header.title, "TESTGAME");
+    assert_eq!(header.manufacturer_code, Some("ABCD".to_owned()));
+    assert_eq!(header.new_licensee_code, Some("01".to_owned()));
+    assert_eq!(header.cgb_flag, CgbFlag::CgbSupported);
+    assert!(header.sgb_flag);
+    assert_eq!(header.cart_type(), Some(CartType::Mbc5RamBattery));
+    assert_eq!(header.rom_size(), Some(RomSize::Rom32KB));
+    assert_eq!(header.ram_size(), Some(RamSize::Ram32KB));
+    assert_eq!(header.destination, Destination::Overseas);
+    assert_eq!(header.version, 2);
+    assert!(header.warnings.is_empty());
+  }
+
+  #[test]
+  fn test_warnings() {
+    let mut data = test_rom();
+    data[0x0104] = 0;
+    data[0x0147] = 0x42;
+
+    let header = CartridgeHeader::parse(&data).unwrap();
+    assert!(!header.logo_valid());
+    assert!(!header.header_checksum_valid());
+    assert!(!header.global_checksum_valid());
+    assert!(header.warnings.contains(&HeaderWarning::UnknownCartType(0x42)));
+
+    let header = CartridgeHeader::parse(&data[..0x4000]).unwrap();
+    assert!(header.warnings.contains(&HeaderWarning::RomSizeMismatch {
+      expected: 0x8000,
+      actual: 0x4000,
+    }));
+
+    assert!(CartridgeHeader::parse(&data[..0x014F]).is_err());
+  }
+
+  #[test]
+  fn test_mmm01() {
+    let mut data = vec![0; 0x8000];
+    data.extend_from_slice(&test_rom());
+    data[0x8147] = 0x0D;
+
+    let header = CartridgeHeader::parse(&data).unwrap();
+    assert_eq!(header.cart_type(), Some(CartType::Mmm01RamBattery));
+    assert_eq!(header.title, "TESTGAME");
+  }
+}
@@ -0,0 +1,280 @@
+use super::rtc::{ClockSource, RtcMode};
+
+// Size of the clock footer SameBoy appends to HuC3 save files.
+pub const SAVE_LEN: usize = 17;
+
+const MINUTES_PER_DAY: u16 = 1440;
+
+// Nibble addresses of the clock registers.
+const MINUTES: u8 = 0x00;
+const DAYS: u8 = 0x03;
+const ALARM_MINUTES: u8 = 0x58;
+const ALARM_DAYS: u8 = 0x5B;
+const ALARM_ENABLED: u8 = 0x5F;
+
+// HuC3 real time clock. Games talk to it through 0xA000-0xBFFF, with
+// the mode written to 0x0000-0x1FFF selecting how:
+//
+// 0x0B  Write a command: upper nibble is the command, lower the argument
+//         1  Read the nibble at the address into the result, increment
+//         2  Write the argument to the address
+//         3  Write the argument to the address, increment
+//         4  Set the lower nibble of the address
+//         5  Set the upper nibble of the address
+//         6  Set the access flags, 2 makes results read as 1 (ready)
+// 0x0C  Read the result of the last command
+// 0x0D  Semaphore, always reads 1 since commands finish immediately
+//
+// Nibbles 0x00-0x02 are the minute of the day, 0x03-0x06 the day
+// counter, and 0x58-0x5F the alarm. The rest is plain memory.
+pub struct Huc3Clock {
+  source: ClockSource,
+
+  minutes: u16,
+  days: u16,
+  memory: [u8; 0x100],
+
+  address: u8,
+  flags: u8,
+  result: u8,
+
+  // Seconds since the last minute passed, the registers only count
+  // minutes.
+  seconds: u64,
+}
+
+impl Huc3Clock {
+  pub fn new(mode: RtcMode) -> Huc3Clock {
+    Huc3Clock {
+      source: ClockSource::new(mode),
+      minutes: 0,
+      days: 0,
+      memory: [0; 0x100],
+      address: 0,
+      flags: 0,
+      result: 0,
+      seconds: 0,
+    }
+  }
+
+  pub fn set_mode(&mut self, mode: RtcMode) {
+    self.update();
+    self.source.set_mode(mode);
+  }
+
+  // Called for every system step.
+  pub fn step(&mut self) {
+    if self.source.step() {
+      self.advance_seconds(1);
+    }
+  }
+
+  // Catches the clock up with the host's clock.
+  fn update(&mut self) {
+    let elapsed = self.source.elapsed();
+    self.advance_seconds(elapsed);
+  }
+
+  fn advance_seconds(&mut self, seconds: u64) {
+    let total = self.seconds + seconds;
+    self.seconds = total % 60;
+    self.advance_minutes(total / 60);
+  }
+
+  pub fn advance_minutes(&mut self, minutes: u64) {
+    let total = self.minutes as u64 + minutes;
+    self.minutes = (total % MINUTES_PER_DAY as u64) as u16;
+    self.days = self.days.wrapping_add((total / MINUTES_PER_DAY as u64) as u16);
+  }
+
+  fn read_nibble(&self, address: u8) -> u8 {
+    match address {
+      0x00...0x02 => (self.minutes >> ((address - MINUTES) * 4)) as u8 & 0x0F,
+      0x03...0x06 => (self.days >> ((address - DAYS) * 4)) as u8 & 0x0F,
+      _ => self.memory[address as usize],
+    }
+  }
+
+  fn write_nibble(&mut self, address: u8, value: u8) {
+    let value = value & 0x0F;
+    match address {
+      0x00...0x02 => {
+        let shift = (address - MINUTES) * 4;
+        self.minutes = (self.minutes & !(0x0F << shift)) | (value as u16) << shift;
+      }
+      0x03...0x06 => {
+        let shift = (address - DAYS) * 4;
+        self.days = (self.days & !(0x0F << shift)) | (value as u16) << shift;
+      }
+      _ => self.memory[address as usize] = value,
+    }
+  }
+
+  // Handles writes to 0xA000-0xBFFF in command mode.
+  pub fn command(&mut self, value: u8) {
+    self.update();
+
+    let arg = value & 0x0F;
+    match value >> 4 {
+      0x1 => {
+        self.result = self.read_nibble(self.address);
+        self.address = self.address.wrapping_add(1);
+      }
+      0x2 => {
+        let address = self.address;
+        self.write_nibble(address, arg);
+      }
+      0x3 => {
+        let address = self.address;
+        self.write_nibble(address, arg);
+        self.address = self.address.wrapping_add(1);
+      }
+      0x4 => self.address = (self.address & 0xF0) | arg,
+      0x5 => self.address = (self.address & 0x0F) | arg << 4,
+      0x6 => self.flags = arg,
+      _ => (),
+    }
+  }
+
+  // Handles reads from 0xA000-0xBFFF in result mode.
+  pub fn result(&self) -> u8 {
+    if self.flags == 0x2 { 0x01 } else { self.result }
+  }
+
+  fn read_u16(&self, address: u8, nibbles: u8) -> u16 {
+    (0..nibbles).fold(0, |v, i| v | (self.read_nibble(address + i) as u16) << (i * 4))
+  }
+
+  fn write_u16(&mut self, address: u8, nibbles: u8, value: u16) {
+    for i in 0..nibbles {
+      self.write_nibble(address + i, (value >> (i * 4)) as u8);
+    }
+  }
+
+  // Serializes the clock in the save file footer format:
+  //
+  // u64 LE  unix timestamp the clock registers are current as of
+  // u16 LE  minute of the day
+  // u16 LE  days
+  // u16 LE  alarm minute of the day
+  // u16 LE  alarm days
+  // u8      alarm enabled
+  pub fn save(&mut self) -> Vec<u8> {
+    self.update();
+
+    // The footer has no seconds, so the time is rolled back to the last
+    // whole minute instead of losing them.
+    let timestamp = self.source.timestamp() - self.seconds;
+    let mut data = Vec::with_capacity(SAVE_LEN);
+    for i in 0..8 {
+      data.push((timestamp >> (i * 8)) as u8);
+    }
+    let alarm_minutes = self.read_u16(ALARM_MINUTES, 3);
+    let alarm_days = self.read_u16(ALARM_DAYS, 4);
+    for &v in &[self.minutes, self.days, alarm_minutes, alarm_days] {
+      data.extend_from_slice(&[v as u8, (v >> 8) as u8]);
+    }
+    data.push(self.memory[ALARM_ENABLED as usize]);
+    data
+  }
+
+  // Restores the clock from the save file footer. In wall clock mode
+  // the time that passed since the save was written is added to it.
+  pub fn load(&mut self, data: &[u8]) -> Result<(), String> {
+    if data.len() != SAVE_LEN {
+      return Err(format!("huc3.load: unexpected clock data length: {}", data.len()));
+    }
+
+    let mut timestamp = 0u64;
+    for i in 0..8 {
+      timestamp |= (data[i] as u64) << (i * 8);
+    }
+    let u16_at = |pos: usize| data[pos] as u16 | (data[pos + 1] as u16) << 8;
+
+    self.minutes = u16_at(8) % MINUTES_PER_DAY;
+    self.days = u16_at(10);
+    let (alarm_minutes, alarm_days) = (u16_at(12), u16_at(14));
+    self.write_u16(ALARM_MINUTES, 3, alarm_minutes);
+    self.write_u16(ALARM_DAYS, 4, alarm_days);
+    self.memory[ALARM_ENABLED as usize] = data[16] & 0x0F;
+    self.seconds = 0;
+
+    self.source.load(timestamp);
+    self.update();
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::rtc::{self, RtcMode};
+
+  #[test]
+  fn test_commands() {
+    let mut clock = Huc3Clock::new(RtcMode::Emulated);
+    clock.advance_minutes(2 * 1440 + 0x123);
+
+    // Read the minutes and days, starting at address 0.
+    clock.command(0x40);
+    clock.command(0x50);
+    let mut nibbles = vec![];
+    for _ in 0..7 {
+      clock.command(0x10);
+      nibbles.push(clock.result());
+    }
+    assert_eq!(nibbles, vec![0x3, 0x2, 0x1, 0x2, 0x0, 0x0, 0x0]);
+
+    // Write the days.
+    clock.command(0x43);
+    clock.command(0x35);
+    clock.command(0x31);
+    assert_eq!(clock.days, 0x15);
+
+    clock.command(0x62);
+    assert_eq!(clock.result(), 0x01);
+  }
+
+  #[test]
+  fn test_save_and_load() {
+    let mut clock = Huc3Clock::new(RtcMode::WallClock);
+    clock.advance_minutes(1439);
+    let mut data = clock.save();
+    assert_eq!(data.len(), SAVE_LEN);
+
+    // Saved two minutes ago.
+    let timestamp = rtc::unix_time() - 120;
+    for i in 0..8 {
+      data[i] = (timestamp >> (i * 8)) as u8;
+    }
+
+    let mut loaded = Huc3Clock::new(RtcMode::WallClock);
+    loaded.load(&data).unwrap();
+    assert_eq!(loaded.minutes, 1);
+    assert_eq!(loaded.days, 1);
+
+    assert!(loaded.load(&data[..16]).is_err());
+  }
+
+  #[test]
+  fn test_save_keeps_seconds() {
+    let mut clock = Huc3Clock::new(RtcMode::WallClock);
+    let mut data = clock.save();
+
+    // Saved 90 seconds ago, so a minute and 30 seconds passed.
+    let timestamp = rtc::unix_time() - 90;
+    for i in 0..8 {
+      data[i] = (timestamp >> (i * 8)) as u8;
+    }
+    clock.load(&data).unwrap();
+    assert_eq!(clock.minutes, 1);
+
+    // Saving again rolls back to the last whole minute.
+    let data = clock.save();
+    let mut saved = 0u64;
+    for i in 0..8 {
+      saved |= (data[i] as u64) << (i * 8);
+    }
+    assert_eq!(saved, timestamp + 60);
+  }
+}
@@ -4,8 +4,26 @@
 use num;
 
 use super::super::mem::MemoryIo;
+use super::CartridgeError;
+use super::camera::{Camera, CameraSource};
+use super::header;
+use super::huc3::Huc3Clock;
 use super::ram;
 use super::rom;
+use super::rtc::{Rtc, RtcMode};
+use super::tama5::{self, Tama5};
+
+// Values written to 0x0000-0x1FFF on HuC1 and HuC3 carts.
+const HUC3_RAM_READ: u8 = 0x00;
+const HUC3_RAM: u8 = 0x0A;
+const HUC3_COMMAND: u8 = 0x0B;
+const HUC3_RESULT: u8 = 0x0C;
+const HUC3_SEMAPHORE: u8 = 0x0D;
+const HUC_IR: u8 = 0x0E;
+
+// What the infrared port reads as when it doesn't see any light. There
+// is no infrared link, so nothing is ever received.
+const IR_NO_LIGHT: u8 = 0xC0;
 
 #[derive(Debug, PartialEq, NumFromPrimitive)]
 enum Mode {
@@ -20,16 +38,44 @@ pub enum MbcType {
   Mbc2,
   Mbc3,
   Mbc5,
+  Huc1,
+  Huc3,
+  Mmm01,
+  Tama5,
+  PocketCamera,
 }
 
 pub struct Mbc {
   mbc_type: MbcType,
   rom: Box<[u8]>,
+  // Always a power of two, so bank numbers can be wrapped with a mask.
+  rom_banks: usize,
   ram: Vec<u8>,
   ram_enabled: bool,
+  // Set when the ram is written, so frontends know when to save it.
+  ram_dirty: bool,
   rom_bank_lower: u8,
   bank_upper: u8,
+  // 9th bit of the MBC5 rom bank
+  rom_bank_high: u8,
+  // MBC1M multicarts only wire 4 bits of the lower bank register.
+  multicart: bool,
   mode: Mode,
+  rtc: Option<Rtc>,
+  rtc_mode: RtcMode,
+  has_rumble: bool,
+  rumble: bool,
+  // HuC1 and HuC3 select what 0xA000-0xBFFF maps to through
+  // 0x0000-0x1FFF instead of enabling the ram.
+  huc_mode: u8,
+  huc3: Option<Huc3Clock>,
+  ir_led: bool,
+  // MMM01 starts out mapping the menu in the last 32KB, until the menu
+  // picks a game and locks the mapper.
+  mmm01_locked: bool,
+  mmm01_base: usize,
+  tama5: Option<Tama5>,
+  camera: Option<Camera>,
 }
 
 impl Default for Mbc {
@@ -37,11 +83,26 @@ impl Default for Mbc {
     Mbc {
       mbc_type: MbcType::None,
       rom: Box::new([]),
+      rom_banks: 0,
       ram: vec![],
       ram_enabled: false,
+      ram_dirty: false,
       rom_bank_lower: 0x1,
       bank_upper: 0,
+      rom_bank_high: 0,
+      multicart: false,
       mode: Mode::RomBank,
+      rtc: None,
+      rtc_mode: RtcMode::WallClock,
+      has_rumble: false,
+      rumble: false,
+      huc_mode: 0,
+      huc3: None,
+      ir_led: false,
+      mmm01_locked: false,
+      mmm01_base: 0,
+      tama5: None,
+      camera: None,
     }
   }
 }
@@ -52,53 +113,205 @@ impl MemoryIo for Mbc {
       MbcType::None => {
         match addr {
           0...0x7FFF => Ok(self.rom[addr as usize]),
+          // Carts without a mbc have no ram enable register, so their
+          // ram is always accessible.
+          0xA000...0xBFFF => Ok(self.ram.get((addr as usize) - 0xA000).cloned().unwrap_or(0xFF)),
+          _ => Ok(0),
+        }
+      }
+      MbcType::Mbc1 => {
+        match addr {
+          // First 16Kbytes of cartrige ROM. In mode 1 the upper bank bits
+          // also apply here.
+          0x0000...0x3FFF => {
+            let bank = if self.mode == Mode::RamBank { self.mbc1_upper_bank() } else { 0 };
+            Ok(self.rom[self.rom_offset(bank, addr)])
+          }
+          0x4000...0x7FFF => {
+            let bank = self.mbc1_upper_bank() | self.mbc1_lower_bank();
+            Ok(self.rom[self.rom_offset(bank, addr)])
+          }
           0xA000...0xBFFF => {
             if !self.ram_enabled {
-              return Ok(0);
+              return Ok(0xFF);
             }
-
-            Ok(self.rom[(addr as usize) - 0xA000])
+            Ok(self.ram.get(self.mbc1_ram_offset(addr)).cloned().unwrap_or(0xFF))
           }
           _ => Ok(0),
         }
       }
-      MbcType::Mbc1 => {
+      MbcType::Mbc2 => {
         match addr {
-          // First 16Kbytes of cartrige ROM.
           0x0000...0x3FFF => Ok(self.rom[addr as usize]),
           0x4000...0x7FFF => {
-            let mut bank = self.rom_bank_lower;
-            if self.mode == Mode::RomBank {
-              bank |= self.bank_upper << 4;
+            Ok(self.rom[self.rom_offset(self.rom_bank_lower as usize, addr)])
+          }
+          // 512x4 bits of built-in ram, echoed across the whole area.
+          // Only the lower 4 bits are wired, the upper bits read as 1s.
+          0xA000...0xBFFF => {
+            if !self.ram_enabled {
+              return Ok(0xFF);
             }
-            let mut loc = (addr as usize) - 0x4000;
-            loc += (bank as usize) * rom::ROM_BANK_SIZE;
-
-            // println!("reading mem loc: {:#04x}, addr: {:#04x}, bank: {:#04x}, value: {:#02x}",
-            //          loc,
-            //          addr,
-            //          bank,
-            //          self.rom[loc]);
-            Ok(self.rom[loc])
+            let loc = ((addr as usize) - 0xA000) & 0x1FF;
+            Ok(self.ram[loc] | 0xF0)
+          }
+          _ => Ok(0),
+        }
+      }
+      MbcType::Mbc3 => {
+        match addr {
+          0x0000...0x3FFF => Ok(self.rom[addr as usize]),
+          0x4000...0x7FFF => {
+            Ok(self.rom[self.rom_offset(self.rom_bank_lower as usize, addr)])
           }
           0xA000...0xBFFF => {
             if !self.ram_enabled {
-              return Ok(0);
+              return Ok(0xFF);
+            }
+            match self.bank_upper {
+              0x00...0x03 => {
+                let mut loc = (addr as usize) - 0xA000;
+                loc += self.bank_upper as usize * ram::RAM_BANK_SIZE;
+                Ok(self.ram.get(loc).cloned().unwrap_or(0xFF))
+              }
+              0x08...0x0C => {
+                match self.rtc {
+                  Some(ref rtc) => Ok(rtc.read(self.bank_upper)),
+                  None => Ok(0xFF),
+                }
+              }
+              _ => Ok(0xFF),
+            }
+          }
+          _ => Ok(0),
+        }
+      }
+      MbcType::Mbc5 => {
+        match addr {
+          0x0000...0x3FFF => Ok(self.rom[addr as usize]),
+          0x4000...0x7FFF => {
+            let bank = (self.rom_bank_high as usize) << 8 | self.rom_bank_lower as usize;
+            Ok(self.rom[self.rom_offset(bank, addr)])
+          }
+          0xA000...0xBFFF => {
+            if !self.ram_enabled {
+              return Ok(0xFF);
+            }
+            let mut loc = (addr as usize) - 0xA000;
+            loc += self.bank_upper as usize * ram::RAM_BANK_SIZE;
+            Ok(self.ram.get(loc).cloned().unwrap_or(0xFF))
+          }
+          _ => Ok(0),
+        }
+      }
+      MbcType::Huc1 => {
+        match addr {
+          0x0000...0x3FFF => Ok(self.rom[addr as usize]),
+          0x4000...0x7FFF => {
+            Ok(self.rom[self.rom_offset(self.rom_bank_lower as usize, addr)])
+          }
+          0xA000...0xBFFF => {
+            if self.huc_mode == HUC_IR {
+              return Ok(IR_NO_LIGHT);
             }
             let mut loc = (addr as usize) - 0xA000;
             loc += self.bank_upper as usize * ram::RAM_BANK_SIZE;
-            Ok(self.ram[loc])
+            Ok(self.ram.get(loc).cloned().unwrap_or(0xFF))
+          }
+          _ => Ok(0),
+        }
+      }
+      MbcType::Huc3 => {
+        match addr {
+          0x0000...0x3FFF => Ok(self.rom[addr as usize]),
+          0x4000...0x7FFF => {
+            Ok(self.rom[self.rom_offset(self.rom_bank_lower as usize, addr)])
+          }
+          0xA000...0xBFFF => {
+            match self.huc_mode {
+              HUC3_RAM_READ | HUC3_RAM => {
+                let mut loc = (addr as usize) - 0xA000;
+                loc += self.bank_upper as usize * ram::RAM_BANK_SIZE;
+                Ok(self.ram.get(loc).cloned().unwrap_or(0xFF))
+              }
+              HUC3_RESULT => Ok(self.huc3.as_ref().map_or(0xFF, |clock| clock.result())),
+              HUC3_SEMAPHORE => Ok(0x01),
+              HUC_IR => Ok(IR_NO_LIGHT),
+              _ => Ok(0xFF),
+            }
+          }
+          _ => Ok(0),
+        }
+      }
+      MbcType::Mmm01 => {
+        match addr {
+          0x0000...0x3FFF => {
+            let bank = if self.mmm01_locked { self.mmm01_base } else { self.rom_banks - 2 };
+            Ok(self.rom[self.rom_offset(bank, addr)])
+          }
+          0x4000...0x7FFF => {
+            let bank = if self.mmm01_locked {
+              self.mmm01_base + self.rom_bank_lower as usize
+            } else {
+              self.rom_banks - 1
+            };
+            Ok(self.rom[self.rom_offset(bank, addr)])
+          }
+          0xA000...0xBFFF => {
+            if !self.ram_enabled {
+              return Ok(0xFF);
+            }
+            let mut loc = (addr as usize) - 0xA000;
+            loc += self.bank_upper as usize * ram::RAM_BANK_SIZE;
+            Ok(self.ram.get(loc).cloned().unwrap_or(0xFF))
+          }
+          _ => Ok(0),
+        }
+      }
+      MbcType::Tama5 => {
+        match addr {
+          0x0000...0x3FFF => Ok(self.rom[addr as usize]),
+          0x4000...0x7FFF => {
+            let bank = self.tama5.as_ref().map_or(1, |tama5| tama5.rom_bank());
+            Ok(self.rom[self.rom_offset(bank, addr)])
+          }
+          0xA000...0xBFFF => Ok(self.tama5.as_ref().map_or(0xFF, |tama5| tama5.read(&self.ram))),
+          _ => Ok(0),
+        }
+      }
+      MbcType::PocketCamera => {
+        match addr {
+          0x0000...0x3FFF => Ok(self.rom[addr as usize]),
+          0x4000...0x7FFF => {
+            Ok(self.rom[self.rom_offset(self.rom_bank_lower as usize, addr)])
+          }
+          // Bit 4 of the ram bank maps the sensor registers instead. The
+          // ram can be read even when it isn't enabled.
+          0xA000...0xBFFF => {
+            if self.bank_upper & 0x10 != 0 {
+              return Ok(self.camera.as_ref().map_or(0x00, |camera| camera.read(addr)));
+            }
+            let mut loc = (addr as usize) - 0xA000;
+            loc += self.bank_upper as usize * ram::RAM_BANK_SIZE;
+            Ok(self.ram.get(loc).cloned().unwrap_or(0xFF))
           }
           _ => Ok(0),
         }
       }
-      _ => Err(format!("mbc.read_u8: unsupported mbc type: {:?}", self.mbc_type)),
     }
   }
 
   fn write_u8(&mut self, addr: u16, value: u8) -> Result<(), String> {
     match self.mbc_type {
-      MbcType::None => Ok(()),
+      MbcType::None => {
+        if let 0xA000...0xBFFF = addr {
+          if let Some(b) = self.ram.get_mut((addr as usize) - 0xA000) {
+            *b = value;
+            self.ram_dirty = true;
+          }
+        }
+        Ok(())
+      }
       MbcType::Mbc1 => {
         match addr {
           // RAM Enable (write only)
@@ -124,60 +337,858 @@ impl MemoryIo for Mbc {
             };
             Ok(())
           }
+          // Writes are ignored while the ram is disabled.
+          0xA000...0xBFFF => {
+            if self.ram_enabled {
+              let loc = self.mbc1_ram_offset(addr);
+              if let Some(b) = self.ram.get_mut(loc) {
+                *b = value;
+                self.ram_dirty = true;
+              }
+            }
+            Ok(())
+          }
+          _ => Ok(()),
+        }
+      }
+      MbcType::Mbc2 => {
+        match addr {
+          // The least significant bit of the upper address byte selects
+          // between ram enable (0) and the rom bank number (1).
+          0x0000...0x3FFF => {
+            if addr & 0x0100 == 0 {
+              self.ram_enabled = value & 0b00001111 == 0x0a;
+            } else {
+              self.rom_bank_lower = value & 0x0f;
+              if self.rom_bank_lower == 0x00 {
+                self.rom_bank_lower = 0x01;
+              }
+            }
+            Ok(())
+          }
+          0xA000...0xBFFF => {
+            if self.ram_enabled {
+              let loc = ((addr as usize) - 0xA000) & 0x1FF;
+              self.ram[loc] = value & 0x0F;
+              self.ram_dirty = true;
+            }
+            Ok(())
+          }
+          _ => Ok(()),
+        }
+      }
+      MbcType::Mbc3 => {
+        match addr {
+          // RAM and RTC Enable (write only)
+          0x0000...0x1FFF => {
+            self.ram_enabled = value & 0b00001111 == 0x0a;
+            Ok(())
+          }
+          0x2000...0x3FFF => {
+            self.rom_bank_lower = value & 0x7f;
+            if self.rom_bank_lower == 0x00 {
+              self.rom_bank_lower = 0x01;
+            }
+            Ok(())
+          }
+          // RAM bank (0x00-0x03) or RTC register (0x08-0x0C)
+          0x4000...0x5FFF => {
+            self.bank_upper = value;
+            Ok(())
+          }
+          0x6000...0x7FFF => {
+            if let Some(ref mut rtc) = self.rtc {
+              rtc.write_latch(value);
+            }
+            Ok(())
+          }
           0xA000...0xBFFF => {
             if !self.ram_enabled {
-              return Err("mbc.write_u8: tried to write to Mbc1 ram when it wasn't enabled"
-                .to_owned());
+              return Ok(());
             }
-
-            let mut loc = addr as usize - 0xA000;
-            if self.mode == Mode::RamBank {
-              loc += self.bank_upper as usize * 0x2000;
+            match self.bank_upper {
+              0x00...0x03 => {
+                let mut loc = (addr as usize) - 0xA000;
+                loc += self.bank_upper as usize * ram::RAM_BANK_SIZE;
+                if let Some(b) = self.ram.get_mut(loc) {
+                  *b = value;
+                  self.ram_dirty = true;
+                }
+              }
+              0x08...0x0C => {
+                if let Some(ref mut rtc) = self.rtc {
+                  rtc.write(self.bank_upper, value);
+                  self.ram_dirty = true;
+                }
+              }
+              _ => (),
+            }
+            Ok(())
+          }
+          _ => Ok(()),
+        }
+      }
+      MbcType::Mbc5 => {
+        match addr {
+          // RAM Enable (write only)
+          0x0000...0x1FFF => {
+            self.ram_enabled = value & 0b00001111 == 0x0a;
+            Ok(())
+          }
+          // Lower 8 bits of the rom bank. Unlike the other MBCs, bank 0
+          // can be selected here.
+          0x2000...0x2FFF => {
+            self.rom_bank_lower = value;
+            Ok(())
+          }
+          0x3000...0x3FFF => {
+            self.rom_bank_high = value & 0x01;
+            Ok(())
+          }
+          // RAM bank. On rumble carts bit 3 drives the motor instead.
+          0x4000...0x5FFF => {
+            if self.has_rumble {
+              self.rumble = value & 0x08 != 0;
+              self.bank_upper = value & 0x07;
+            } else {
+              self.bank_upper = value & 0x0f;
+            }
+            Ok(())
+          }
+          0xA000...0xBFFF => {
+            if self.ram_enabled {
+              let mut loc = (addr as usize) - 0xA000;
+              loc += self.bank_upper as usize * ram::RAM_BANK_SIZE;
+              if let Some(b) = self.ram.get_mut(loc) {
+                *b = value;
+                self.ram_dirty = true;
+              }
+            }
+            Ok(())
+          }
+          _ => Ok(()),
+        }
+      }
+      MbcType::Huc1 => {
+        match addr {
+          0x0000...0x1FFF => {
+            self.huc_mode = value & 0x0F;
+            Ok(())
+          }
+          0x2000...0x3FFF => {
+            self.rom_bank_lower = value & 0x3f;
+            if self.rom_bank_lower == 0x00 {
+              self.rom_bank_lower = 0x01;
+            }
+            Ok(())
+          }
+          0x4000...0x5FFF => {
+            self.bank_upper = value & 0x03;
+            Ok(())
+          }
+          0xA000...0xBFFF => {
+            if self.huc_mode == HUC_IR {
+              self.ir_led = value & 0x01 != 0;
+              return Ok(());
+            }
+            let mut loc = (addr as usize) - 0xA000;
+            loc += self.bank_upper as usize * ram::RAM_BANK_SIZE;
+            if let Some(b) = self.ram.get_mut(loc) {
+              *b = value;
+              self.ram_dirty = true;
+            }
+            Ok(())
+          }
+          _ => Ok(()),
+        }
+      }
+      MbcType::Huc3 => {
+        match addr {
+          0x0000...0x1FFF => {
+            self.huc_mode = value & 0x0F;
+            Ok(())
+          }
+          0x2000...0x3FFF => {
+            self.rom_bank_lower = value & 0x7f;
+            if self.rom_bank_lower == 0x00 {
+              self.rom_bank_lower = 0x01;
+            }
+            Ok(())
+          }
+          0x4000...0x5FFF => {
+            self.bank_upper = value & 0x03;
+            Ok(())
+          }
+          0xA000...0xBFFF => {
+            match self.huc_mode {
+              HUC3_RAM => {
+                let mut loc = (addr as usize) - 0xA000;
+                loc += self.bank_upper as usize * ram::RAM_BANK_SIZE;
+                if let Some(b) = self.ram.get_mut(loc) {
+                  *b = value;
+                  self.ram_dirty = true;
+                }
+              }
+              HUC3_COMMAND => {
+                if let Some(ref mut clock) = self.huc3 {
+                  clock.command(value);
+                  self.ram_dirty = true;
+                }
+              }
+              HUC_IR => self.ir_led = value & 0x01 != 0,
+              _ => (),
+            }
+            Ok(())
+          }
+          _ => Ok(()),
+        }
+      }
+      // Until it is locked, the MMM01 registers set the first bank of the
+      // game. Writing a value with bit 6 set to 0x0000-0x1FFF locks it,
+      // after which it works like an MBC1 relative to that bank. Ram is
+      // enabled by the low nibble either way.
+      MbcType::Mmm01 if !self.mmm01_locked && addr < 0x6000 => {
+        match addr {
+          0x0000...0x1FFF => {
+            self.ram_enabled = value & 0b00001111 == 0x0a;
+            self.mmm01_locked = value & 0x40 != 0;
+            Ok(())
+          }
+          0x2000...0x3FFF => {
+            self.mmm01_base = (self.mmm01_base & !0x7F) | (value & 0x7F) as usize;
+            Ok(())
+          }
+          0x4000...0x5FFF => {
+            self.mmm01_base = (self.mmm01_base & 0x7F) | ((value & 0x30) as usize) << 3;
+            Ok(())
+          }
+          _ => unreachable!(),
+        }
+      }
+      MbcType::Mmm01 => {
+        match addr {
+          0x0000...0x1FFF => {
+            self.ram_enabled = value & 0b00001111 == 0x0a;
+            Ok(())
+          }
+          0x2000...0x3FFF => {
+            self.rom_bank_lower = value & 0x1f;
+            if self.rom_bank_lower == 0x00 {
+              self.rom_bank_lower = 0x01;
+            }
+            Ok(())
+          }
+          0x4000...0x5FFF => {
+            self.bank_upper = value & 0x03;
+            Ok(())
+          }
+          0xA000...0xBFFF => {
+            if self.ram_enabled {
+              let mut loc = (addr as usize) - 0xA000;
+              loc += self.bank_upper as usize * ram::RAM_BANK_SIZE;
+              if let Some(b) = self.ram.get_mut(loc) {
+                *b = value;
+                self.ram_dirty = true;
+              }
             }
-            self.ram[loc] = value;
             Ok(())
           }
-          _ => {
-            panic!("cartridge.write_u8: unhandled address: {:#04x}", addr);
+          _ => Ok(()),
+        }
+      }
+      MbcType::Tama5 => {
+        if let 0xA000...0xBFFF = addr {
+          if let Some(ref mut tama5) = self.tama5 {
+            if tama5.write(addr, value, &mut self.ram) {
+              self.ram_dirty = true;
+            }
           }
         }
+        Ok(())
+      }
+      MbcType::PocketCamera => {
+        match addr {
+          0x0000...0x1FFF => {
+            self.ram_enabled = value & 0b00001111 == 0x0a;
+            Ok(())
+          }
+          0x2000...0x3FFF => {
+            self.rom_bank_lower = value & 0x3f;
+            if self.rom_bank_lower == 0x00 {
+              self.rom_bank_lower = 0x01;
+            }
+            Ok(())
+          }
+          // RAM bank (0x00-0x0F) or the sensor registers (0x10)
+          0x4000...0x5FFF => {
+            self.bank_upper = value & 0x1f;
+            Ok(())
+          }
+          0xA000...0xBFFF => {
+            if self.bank_upper & 0x10 != 0 {
+              if let Some(ref mut camera) = self.camera {
+                camera.write(addr, value);
+              }
+            } else if self.ram_enabled {
+              let mut loc = (addr as usize) - 0xA000;
+              loc += self.bank_upper as usize * ram::RAM_BANK_SIZE;
+              if let Some(b) = self.ram.get_mut(loc) {
+                *b = value;
+                self.ram_dirty = true;
+              }
+            }
+            Ok(())
+          }
+          _ => Ok(()),
+        }
       }
-      _ => Err(format!("mbc.write_u8: unsupported mbc type: {:?}", self.mbc_type)),
     }
   }
 }
 
+// Pads the rom with 0xFF to whole banks, then mirrors it up to a power
+// of two amount of banks (at least 2), like the unconnected address
+// lines on a real cartridge would.
+fn normalize_rom(rom: Box<[u8]>) -> Box<[u8]> {
+  let banks = (rom.len() + rom::ROM_BANK_SIZE - 1) / rom::ROM_BANK_SIZE;
+  let mut size = 2;
+  while size < banks {
+    size *= 2;
+  }
+  let size = size * rom::ROM_BANK_SIZE;
+  if size == rom.len() {
+    return rom;
+  }
+
+  let mut data = rom.into_vec();
+  data.resize(banks * rom::ROM_BANK_SIZE, 0xFF);
+  let padded_len = data.len();
+  while data.len() < size {
+    let len = if size - data.len() < padded_len { size - data.len() } else { padded_len };
+    let mirror = data[..len].to_vec();
+    data.extend_from_slice(&mirror);
+  }
+  data.into_boxed_slice()
+}
+
+// MBC1M multicarts are 1MB collections of 256KB games, each of them
+// starting with a header of its own.
+fn is_mbc1_multicart(rom: &[u8]) -> bool {
+  if rom.len() != 0x100000 {
+    return false;
+  }
+  (1..4).any(|game| {
+    let start = game * 0x40000 + 0x0104;
+    rom[start..start + header::LOGO.len()] == header::LOGO[..]
+  })
+}
+
 impl Mbc {
   pub fn new() -> Mbc {
     Mbc::default()
   }
 
-  pub fn load(&mut self, mbc_type: MbcType, rom: Box<[u8]>) -> Result<(), String> {
-    self.mbc_type = mbc_type;
+  fn mbc1_lower_bank(&self) -> usize {
+    if self.multicart {
+      (self.rom_bank_lower & 0x0f) as usize
+    } else {
+      self.rom_bank_lower as usize
+    }
+  }
+
+  // The upper bank bits are bits 5-6 of the rom bank, or bits 4-5 on
+  // multicarts.
+  fn mbc1_upper_bank(&self) -> usize {
+    if self.multicart {
+      (self.bank_upper as usize) << 4
+    } else {
+      (self.bank_upper as usize) << 5
+    }
+  }
 
-    let rom_size: rom::RomSize = match num::FromPrimitive::from_u8(rom[0x148]) {
-      Some(v) => v,
-      None => {
-        return Err(format!("unsupported ram size: {:#02x}", rom[0x0148]));
+  // In mode 1 the upper bank bits select the ram bank instead.
+  fn mbc1_ram_offset(&self, addr: u16) -> usize {
+    let mut loc = (addr as usize) - 0xA000;
+    if self.mode == Mode::RamBank {
+      loc += self.bank_upper as usize * ram::RAM_BANK_SIZE;
+    }
+    loc
+  }
+
+  // Offset into the rom for an address in a 16KB rom bank. Bank numbers
+  // past the end of the rom wrap around.
+  fn rom_offset(&self, bank: usize, addr: u16) -> usize {
+    (bank & (self.rom_banks - 1)) * rom::ROM_BANK_SIZE + (addr as usize & 0x3FFF)
+  }
+
+  // Adds a real time clock to the cartridge.
+  pub fn enable_rtc(&mut self) {
+    match self.mbc_type {
+      MbcType::Huc3 => self.huc3 = Some(Huc3Clock::new(self.rtc_mode)),
+      // The TAMA5 clock is part of the mapper.
+      MbcType::Tama5 => (),
+      _ => self.rtc = Some(Rtc::new(self.rtc_mode)),
+    }
+  }
+
+  pub fn set_rtc_mode(&mut self, mode: RtcMode) {
+    self.rtc_mode = mode;
+    if let Some(ref mut rtc) = self.rtc {
+      rtc.set_mode(mode);
+    }
+    if let Some(ref mut clock) = self.huc3 {
+      clock.set_mode(mode);
+    }
+    if let Some(ref mut tama5) = self.tama5 {
+      tama5.set_mode(mode);
+    }
+  }
+
+  // Adds a rumble motor to the cartridge.
+  pub fn enable_rumble(&mut self) {
+    self.has_rumble = true;
+  }
+
+  // Whether the rumble motor is currently on.
+  pub fn rumble(&self) -> bool {
+    self.rumble
+  }
+
+  // Whether the infrared LED of HuC1 and HuC3 carts is on.
+  pub fn ir_led(&self) -> bool {
+    self.ir_led
+  }
+
+  // Sets where the Game Boy Camera gets its pictures from. Does nothing
+  // for other carts.
+  pub fn set_camera_source(&mut self, source: CameraSource) {
+    if let Some(ref mut camera) = self.camera {
+      camera.set_source(source);
+    }
+  }
+
+  // The contents of the save file: the cartridge ram in the raw format
+  // other emulators use, followed by the clock footer for carts with a
+  // real time clock.
+  pub fn save_ram(&mut self) -> Vec<u8> {
+    let mut data = self.ram.clone();
+    if let Some(ref mut rtc) = self.rtc {
+      data.extend_from_slice(&rtc.save());
+    }
+    if let Some(ref mut clock) = self.huc3 {
+      data.extend_from_slice(&clock.save());
+    }
+    if let Some(ref mut tama5) = self.tama5 {
+      data.extend_from_slice(&tama5.save());
+    }
+    self.ram_dirty = false;
+    data
+  }
+
+  // Restores the ram (and rtc) from a save file. Missing ram data is
+  // left untouched.
+  pub fn load_ram(&mut self, data: &[u8]) -> Result<(), String> {
+    let len = if data.len() < self.ram.len() { data.len() } else { self.ram.len() };
+    self.ram[..len].copy_from_slice(&data[..len]);
+    if let MbcType::Mbc2 = self.mbc_type {
+      for b in &mut self.ram {
+        *b &= 0x0F;
       }
-    };
+    }
 
-    if rom_size.as_usize() != rom.len() {
-      return Err(format!("unexpected rom size: Got: {}, Expected: {}",
-                         rom.len(),
-                         rom_size.as_usize()));
+    if let Some(ref mut rtc) = self.rtc {
+      // Saves without the rtc footer just start the clock from zero.
+      if data.len() > len {
+        try!(rtc.load(&data[len..]));
+      }
+    }
+    if let Some(ref mut clock) = self.huc3 {
+      if data.len() > len {
+        try!(clock.load(&data[len..]));
+      }
+    }
+    if let Some(ref mut tama5) = self.tama5 {
+      if data.len() > len {
+        try!(tama5.load(&data[len..]));
+      }
     }
 
-    let ram_size: ram::RamSize = match num::FromPrimitive::from_u8(rom[0x149]) {
-      Some(v) => v,
-      None => {
-        return Err(format!("unsupported ram size: {:#02x}", rom[0x0149]));
+    self.ram_dirty = false;
+    Ok(())
+  }
+
+  pub fn ram_dirty(&self) -> bool {
+    self.ram_dirty
+  }
+
+  pub fn step(&mut self) {
+    if let Some(ref mut rtc) = self.rtc {
+      rtc.step();
+    }
+    if let Some(ref mut clock) = self.huc3 {
+      clock.step();
+    }
+    if let Some(ref mut tama5) = self.tama5 {
+      tama5.step();
+    }
+    if let Some(ref mut camera) = self.camera {
+      if camera.step(&mut self.ram) {
+        self.ram_dirty = true;
       }
+    }
+  }
+
+  pub fn load(&mut self, mbc_type: MbcType, rom: Box<[u8]>) -> Result<(), CartridgeError> {
+    // The header's rom size is ignored, plenty of homebrew, trimmed and
+    // overdumped roms get it wrong. The header parser warns about it.
+    let rom = normalize_rom(rom);
+
+    // The MMM01 header is the one of the menu in the last 32KB.
+    let header_start = match mbc_type {
+      MbcType::Mmm01 => rom.len() - 2 * rom::ROM_BANK_SIZE,
+      _ => 0,
+    };
+    let ram_size_code = rom[header_start + 0x0149];
+    let ram_size: ram::RamSize = match num::FromPrimitive::from_u8(ram_size_code) {
+      Some(v) => v,
+      None => return Err(CartridgeError::UnknownRamSize(ram_size_code)),
+    };
+
+    // MBC2 has 512x4 bits of ram built in, and the header says there
+    // is no ram. The TAMA5 has 32 bytes.
+    let ram_len = match mbc_type {
+      MbcType::Mbc2 => 512,
+      MbcType::Tama5 => tama5::RAM_LEN,
+      _ => ram_size.as_usize(),
+    };
+    let multicart = match mbc_type {
+      MbcType::Mbc1 => is_mbc1_multicart(&rom),
+      _ => false,
+    };
+    let tama5 = match mbc_type {
+      MbcType::Tama5 => Some(Tama5::new(self.rtc_mode)),
+      _ => None,
+    };
+    let camera = match mbc_type {
+      MbcType::PocketCamera => Some(Camera::new()),
+      _ => None,
     };
 
-    self.rom = From::from(rom);
-    self.ram = vec![0; ram_size.as_usize()];
+    // Nothing of the previous cartridge carries over, except for the
+    // clock mode the frontend picked.
+    *self = Mbc {
+      mbc_type: mbc_type,
+      rom_banks: rom.len() / rom::ROM_BANK_SIZE,
+      rom: rom,
+      ram: vec![0; ram_len],
+      multicart: multicart,
+      rtc_mode: self.rtc_mode,
+      tama5: tama5,
+      camera: camera,
+      ..Mbc::default()
+    };
 
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::super::mem::MemoryIo;
+  use super::super::{header, huc3, ram, rom, tama5};
+  use super::super::rtc::RtcMode;
+
+  // Builds a rom where the first byte of every bank is the bank number.
+  fn test_rom(rom_size: u8, ram_size: u8, banks: usize) -> Box<[u8]> {
+    let mut data = vec![0; banks * rom::ROM_BANK_SIZE];
+    for bank in 0..banks {
+      data[bank * rom::ROM_BANK_SIZE] = bank as u8;
+    }
+    data[0x148] = rom_size;
+    data[0x149] = ram_size;
+    data.into_boxed_slice()
+  }
+
+  #[test]
+  fn test_mbc1() {
+    let mut mbc = Mbc::new();
+    mbc.load(MbcType::Mbc1, test_rom(0x05, 0x03, 64)).unwrap();
+
+    // The upper bits apply to 0x4000-0x7FFF in both modes.
+    mbc.write_u8(0x2000, 0x02).unwrap();
+    mbc.write_u8(0x4000, 0x01).unwrap();
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x22);
+    assert_eq!(mbc.read_u8(0x0000).unwrap(), 0x00);
+
+    // Mode 1 also maps them into 0x0000-0x3FFF, and selects the ram bank.
+    mbc.write_u8(0x6000, 0x01).unwrap();
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x22);
+    assert_eq!(mbc.read_u8(0x0000).unwrap(), 0x20);
+
+    mbc.write_u8(0x0000, 0x0a).unwrap();
+    mbc.write_u8(0xA000, 0x11).unwrap();
+    assert_eq!(mbc.ram[ram::RAM_BANK_SIZE], 0x11);
+    mbc.write_u8(0x6000, 0x00).unwrap();
+    assert_eq!(mbc.read_u8(0xA000).unwrap(), 0x00);
+
+    mbc.write_u8(0x0000, 0x00).unwrap();
+    assert_eq!(mbc.read_u8(0xA000).unwrap(), 0xff);
+  }
+
+  #[test]
+  fn test_reload() {
+    let mut mbc = Mbc::new();
+    mbc.load(MbcType::Mbc1, test_rom(0x05, 0x03, 64)).unwrap();
+    mbc.write_u8(0x0000, 0x0a).unwrap();
+    mbc.write_u8(0x2000, 0x02).unwrap();
+    mbc.write_u8(0x4000, 0x01).unwrap();
+    mbc.write_u8(0x6000, 0x01).unwrap();
+    mbc.write_u8(0xA000, 0x11).unwrap();
+
+    // A rom that fails to load leaves the current one alone.
+    assert!(mbc.load(MbcType::Mbc5, test_rom(0x05, 0x07, 64)).is_err());
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x22);
+    assert_eq!(mbc.read_u8(0xA000).unwrap(), 0x11);
+
+    // Reloading starts with the banking and ram enable reset.
+    mbc.load(MbcType::Mbc1, test_rom(0x05, 0x03, 64)).unwrap();
+    assert_eq!(mbc.read_u8(0x0000).unwrap(), 0x00);
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x01);
+    assert_eq!(mbc.read_u8(0xA000).unwrap(), 0xff);
+    mbc.write_u8(0x0000, 0x0a).unwrap();
+    assert_eq!(mbc.read_u8(0xA000).unwrap(), 0x00);
+  }
+
+  #[test]
+  fn test_mbc1_multicart() {
+    let mut rom = test_rom(0x05, 0x00, 64).into_vec();
+    rom[0x40104..0x40134].copy_from_slice(&header::LOGO);
+    let mut mbc = Mbc::new();
+    mbc.load(MbcType::Mbc1, rom.into_boxed_slice()).unwrap();
+    assert!(mbc.multicart);
+
+    // Only 4 bits of the lower bank are wired, the upper bits start at
+    // bit 4.
+    mbc.write_u8(0x2000, 0x12).unwrap();
+    mbc.write_u8(0x4000, 0x01).unwrap();
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x12);
+    mbc.write_u8(0x6000, 0x01).unwrap();
+    assert_eq!(mbc.read_u8(0x0000).unwrap(), 0x10);
+  }
+
+  #[test]
+  fn test_mbc2() {
+    let mut mbc = Mbc::new();
+    mbc.load(MbcType::Mbc2, test_rom(0x03, 0x00, 16)).unwrap();
+
+    // Bit 8 of the address is set, so this selects the rom bank.
+    mbc.write_u8(0x2100, 0x05).unwrap();
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x05);
+    mbc.write_u8(0x2100, 0x00).unwrap();
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x01);
+
+    // Bit 8 of the address is clear, so this enables ram.
+    mbc.write_u8(0x0000, 0x0a).unwrap();
+    mbc.write_u8(0xA001, 0xab).unwrap();
+    assert_eq!(mbc.read_u8(0xA001).unwrap(), 0xfb);
+    // Echoed every 512 bytes.
+    assert_eq!(mbc.read_u8(0xA201).unwrap(), 0xfb);
+    assert_eq!(mbc.read_u8(0xBE01).unwrap(), 0xfb);
+
+    mbc.write_u8(0x0000, 0x00).unwrap();
+    assert_eq!(mbc.read_u8(0xA001).unwrap(), 0xff);
+  }
+
+  #[test]
+  fn test_mbc5() {
+    let mut mbc = Mbc::new();
+    mbc.load(MbcType::Mbc5, test_rom(0x08, 0x04, 512)).unwrap();
+    mbc.enable_rumble();
+
+    // Bank 0 is selectable.
+    mbc.write_u8(0x2000, 0x00).unwrap();
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x00);
+    // The 9th bit selects the upper 256 banks. The test rom only
+    // stores the lower 8 bits of the bank number.
+    mbc.write_u8(0x2000, 0x03).unwrap();
+    mbc.write_u8(0x3000, 0x01).unwrap();
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x03);
+    assert_eq!(mbc.read_u8(0x4001).unwrap(), 0x00);
+
+    mbc.write_u8(0x4000, 0x0a).unwrap();
+    assert!(mbc.rumble());
+    mbc.write_u8(0x4000, 0x02).unwrap();
+    assert!(!mbc.rumble());
+  }
+
+  #[test]
+  fn test_huc1() {
+    let mut mbc = Mbc::new();
+    mbc.load(MbcType::Huc1, test_rom(0x05, 0x03, 64)).unwrap();
+
+    mbc.write_u8(0x2000, 0x3f).unwrap();
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x3f);
+
+    mbc.write_u8(0x4000, 0x02).unwrap();
+    mbc.write_u8(0xA000, 0x12).unwrap();
+    assert_eq!(mbc.ram[2 * ram::RAM_BANK_SIZE], 0x12);
+
+    // The infrared port replaces the ram.
+    mbc.write_u8(0x0000, 0x0e).unwrap();
+    mbc.write_u8(0xA000, 0x01).unwrap();
+    assert!(mbc.ir_led());
+    assert_eq!(mbc.read_u8(0xA000).unwrap(), 0xc0);
+    mbc.write_u8(0x0000, 0x0a).unwrap();
+    assert_eq!(mbc.read_u8(0xA000).unwrap(), 0x12);
+  }
+
+  #[test]
+  fn test_huc3() {
+    let mut mbc = Mbc::new();
+    mbc.load(MbcType::Huc3, test_rom(0x05, 0x03, 64)).unwrap();
+    mbc.set_rtc_mode(RtcMode::Emulated);
+    mbc.enable_rtc();
+
+    mbc.write_u8(0x0000, 0x0a).unwrap();
+    mbc.write_u8(0xA000, 0x34).unwrap();
+    // Read only ram mode.
+    mbc.write_u8(0x0000, 0x00).unwrap();
+    mbc.write_u8(0xA000, 0x56).unwrap();
+    assert_eq!(mbc.read_u8(0xA000).unwrap(), 0x34);
+
+    // Write 0x5 to the lowest nibble of the minutes, then read it back.
+    mbc.write_u8(0x0000, 0x0b).unwrap();
+    for &command in &[0x40, 0x50, 0x35, 0x40, 0x10] {
+      mbc.write_u8(0xA000, command).unwrap();
+    }
+    mbc.write_u8(0x0000, 0x0c).unwrap();
+    assert_eq!(mbc.read_u8(0xA000).unwrap(), 0x05);
+    mbc.write_u8(0x0000, 0x0d).unwrap();
+    assert_eq!(mbc.read_u8(0xA000).unwrap(), 0x01);
+
+    let save = mbc.save_ram();
+    assert_eq!(save.len(), mbc.ram.len() + huc3::SAVE_LEN);
+  }
+
+  #[test]
+  fn test_mmm01() {
+    let mut rom = test_rom(0x05, 0x00, 64).into_vec();
+    // The menu's header, with 8KB of ram.
+    rom[62 * rom::ROM_BANK_SIZE + 0x149] = 0x02;
+    let mut mbc = Mbc::new();
+    mbc.load(MbcType::Mmm01, rom.into_boxed_slice()).unwrap();
+    assert_eq!(mbc.ram.len(), 0x2000);
+
+    // Starts in the menu.
+    assert_eq!(mbc.read_u8(0x0000).unwrap(), 62);
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 63);
+
+    // Ram can be used without locking the mapper.
+    mbc.write_u8(0x0000, 0x0A).unwrap();
+    mbc.write_u8(0xA000, 0x5A).unwrap();
+    assert_eq!(mbc.read_u8(0xA000).unwrap(), 0x5A);
+    mbc.write_u8(0x0000, 0x00).unwrap();
+    assert_eq!(mbc.read_u8(0xA000).unwrap(), 0xFF);
+    assert_eq!(mbc.read_u8(0x0000).unwrap(), 62);
+
+    // The menu picks the game at bank 0x10, then locks the mapper.
+    mbc.write_u8(0x2000, 0x10).unwrap();
+    mbc.write_u8(0x0000, 0x40).unwrap();
+    assert_eq!(mbc.read_u8(0x0000).unwrap(), 0x10);
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x11);
+
+    // Banks are relative to the game from now on.
+    mbc.write_u8(0x2000, 0x03).unwrap();
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x13);
+    assert_eq!(mbc.read_u8(0x0000).unwrap(), 0x10);
+  }
+
+  #[test]
+  fn test_tama5() {
+    let mut mbc = Mbc::new();
+    mbc.load(MbcType::Tama5, test_rom(0x04, 0x00, 32)).unwrap();
+    assert_eq!(mbc.ram.len(), tama5::RAM_LEN);
+
+    // Bank 0x12 through the BANK_LO and BANK_HI registers.
+    for &(reg, value) in &[(0x0, 0x2), (0x1, 0x1)] {
+      mbc.write_u8(0xA001, reg).unwrap();
+      mbc.write_u8(0xA000, value).unwrap();
+    }
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x12);
+
+    // Ram write of 0x5A to 0x01.
+    for &(reg, value) in &[(0x4, 0xA), (0x5, 0x5), (0x6, 0x0), (0x7, 0x1)] {
+      mbc.write_u8(0xA001, reg).unwrap();
+      mbc.write_u8(0xA000, value).unwrap();
+    }
+    assert_eq!(mbc.ram[0x01], 0x5A);
+    assert!(mbc.ram_dirty());
+  }
+
+  #[test]
+  fn test_pocket_camera() {
+    let mut mbc = Mbc::new();
+    mbc.load(MbcType::PocketCamera, test_rom(0x05, 0x04, 64)).unwrap();
+    assert_eq!(mbc.ram.len(), 0x20000);
+
+    mbc.write_u8(0x2000, 0x3f).unwrap();
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x3f);
+
+    mbc.write_u8(0x0000, 0x0a).unwrap();
+    mbc.write_u8(0x4000, 0x0f).unwrap();
+    mbc.write_u8(0xBFFF, 0x12).unwrap();
+    assert_eq!(mbc.ram[0x1FFFF], 0x12);
+
+    // Start a capture through the sensor registers, and wait for it.
+    mbc.write_u8(0x4000, 0x10).unwrap();
+    mbc.write_u8(0xA000, 0x01).unwrap();
+    assert_eq!(mbc.read_u8(0xA000).unwrap(), 0x01);
+    mbc.ram_dirty = false;
+    while mbc.read_u8(0xA000).unwrap() & 0x01 != 0 {
+      mbc.step();
+    }
+    assert!(mbc.ram_dirty());
+  }
+
+  #[test]
+  fn test_rom_size_mismatch() {
+    let mut mbc = Mbc::new();
+    // 3 banks, but the header says 8.
+    mbc.load(MbcType::Mbc5, test_rom(0x02, 0x00, 3)).unwrap();
+    assert_eq!(mbc.rom.len(), 4 * rom::ROM_BANK_SIZE);
+
+    // The 4th bank is a mirror of the first, and the rest wraps.
+    mbc.write_u8(0x2000, 0x03).unwrap();
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x00);
+    mbc.write_u8(0x2000, 0x06).unwrap();
+    assert_eq!(mbc.read_u8(0x4000).unwrap(), 0x02);
+  }
+
+  #[test]
+  fn test_load_ram() {
+    let mut mbc = Mbc::new();
+    mbc.load(MbcType::Mbc5, test_rom(0x00, 0x02, 2)).unwrap();
+    mbc.load_ram(&[0x12, 0x34]).unwrap();
+    assert!(!mbc.ram_dirty());
+
+    mbc.write_u8(0x0000, 0x0a).unwrap();
+    assert_eq!(mbc.read_u8(0xA001).unwrap(), 0x34);
+    mbc.write_u8(0xA002, 0x56).unwrap();
+    assert!(mbc.ram_dirty());
+    assert_eq!(&mbc.save_ram()[..3], &[0x12, 0x34, 0x56]);
+    assert!(!mbc.ram_dirty());
+  }
+
+  #[test]
+  fn test_rtc_save() {
+    let mut mbc = Mbc::new();
+    mbc.load(MbcType::Mbc3, test_rom(0x00, 0x02, 2)).unwrap();
+    mbc.enable_rtc();
+
+    let data = mbc.save_ram();
+    assert_eq!(data.len(), 0x2000 + 48);
+    mbc.load_ram(&data).unwrap();
+    assert!(mbc.load_ram(&data[..0x2000 + 10]).is_err());
+  }
+}
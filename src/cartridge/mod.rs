@@ -1,12 +1,25 @@
 use num::FromPrimitive;
 
+mod camera;
+mod error;
+mod header;
+mod huc3;
 mod ram;
 mod rom;
+mod rtc;
+mod tama5;
 mod mbc;
 
 use self::mbc::MbcType;
 use super::mem::MemoryIo;
 
+pub use self::camera::{CameraSource, CAMERA_WIDTH, CAMERA_HEIGHT};
+pub use self::error::CartridgeError;
+pub use self::header::{CartridgeHeader, HeaderWarning, CgbFlag, Destination};
+pub use self::ram::{RamSize, RAM_BANK_SIZE};
+pub use self::rom::{RomSize, ROM_BANK_SIZE};
+pub use self::rtc::RtcMode;
+
 // 16KB ROM Bank 00
 // In cartridge, fixed at bank 00
 pub const ROM_00_START: u16 = 0x0000;
@@ -20,8 +33,8 @@ pub const ROM_01_END: u16 = 0x7FFF;
 pub const CART_RAM_START: u16 = 0xA000;
 pub const CART_RAM_END: u16 = 0xBFFF;
 
-#[derive(PartialEq, Debug, NumFromPrimitive)]
-enum CartType {
+#[derive(PartialEq, Debug, Clone, Copy, NumFromPrimitive)]
+pub enum CartType {
   RomOnly = 0x00,
   Mbc1 = 0x01,
   Mbc1Ram = 0x02,
@@ -54,9 +67,10 @@ enum CartType {
 }
 
 impl CartType {
-  fn as_mbc_type(&self) -> MbcType {
+  // None for mappers that aren't emulated.
+  fn as_mbc_type(&self) -> Option<MbcType> {
     use self::CartType::*;
-    match *self {
+    let mbc_type = match *self {
       RomOnly | RomRam | RomRamBattery => MbcType::None,
       Mbc1 | Mbc1Ram | Mbc1RamBattery => MbcType::Mbc1,
       Mbc2 | Mbc2Battery => MbcType::Mbc2,
@@ -71,7 +85,76 @@ impl CartType {
       Mbc5Rumble |
       Mbc5RumbleRam |
       Mbc5RumbleRamBattery => MbcType::Mbc5,
-      _ => panic!("unknown mbc type"),
+      Huc1RamBattery => MbcType::Huc1,
+      Huc3 => MbcType::Huc3,
+      Mmm01 | Mmm01Ram | Mmm01RamBattery => MbcType::Mmm01,
+      BandaiTama5 => MbcType::Tama5,
+      PocketCamera => MbcType::PocketCamera,
+      Mbc4 | Mbc4Ram | Mbc4RamBattery => return None,
+    };
+    Some(mbc_type)
+  }
+
+  // Name of the memory bank controller.
+  pub fn mapper_name(&self) -> &'static str {
+    use self::CartType::*;
+    match *self {
+      RomOnly | RomRam | RomRamBattery => "None",
+      Mbc1 | Mbc1Ram | Mbc1RamBattery => "MBC1",
+      Mbc2 | Mbc2Battery => "MBC2",
+      Mmm01 | Mmm01Ram | Mmm01RamBattery => "MMM01",
+      Mbc3 | Mbc3Ram | Mbc3RamBattery | Mbc3TimerBattery | Mbc3TimerRamBattery => "MBC3",
+      Mbc4 | Mbc4Ram | Mbc4RamBattery => "MBC4",
+      Mbc5 | Mbc5Ram | Mbc5RamBattery | Mbc5Rumble | Mbc5RumbleRam | Mbc5RumbleRamBattery => {
+        "MBC5"
+      }
+      PocketCamera => "Pocket Camera",
+      BandaiTama5 => "TAMA5",
+      Huc3 => "HuC3",
+      Huc1RamBattery => "HuC1",
+    }
+  }
+
+  // Whether this emulator can run carts with this mapper.
+  pub fn is_supported(&self) -> bool {
+    self.as_mbc_type().is_some()
+  }
+
+  pub fn has_timer(&self) -> bool {
+    match *self {
+      CartType::Mbc3TimerBattery |
+      CartType::Mbc3TimerRamBattery |
+      CartType::BandaiTama5 |
+      CartType::Huc3 => true,
+      _ => false,
+    }
+  }
+
+  pub fn has_battery(&self) -> bool {
+    use self::CartType::*;
+    match *self {
+      Mbc1RamBattery |
+      Mbc2Battery |
+      RomRamBattery |
+      Mmm01RamBattery |
+      Mbc3TimerBattery |
+      Mbc3TimerRamBattery |
+      Mbc3RamBattery |
+      Mbc4RamBattery |
+      Mbc5RamBattery |
+      Mbc5RumbleRamBattery |
+      PocketCamera |
+      BandaiTama5 |
+      Huc3 |
+      Huc1RamBattery => true,
+      _ => false,
+    }
+  }
+
+  pub fn has_rumble(&self) -> bool {
+    match *self {
+      CartType::Mbc5Rumble | CartType::Mbc5RumbleRam | CartType::Mbc5RumbleRamBattery => true,
+      _ => false,
     }
   }
 }
@@ -80,6 +163,7 @@ pub struct Cartridge {
   mbc: Box<mbc::Mbc>,
   cart_type: CartType,
   title: String,
+  header: Option<CartridgeHeader>,
 }
 
 impl MemoryIo for Cartridge {
@@ -98,6 +182,7 @@ impl Default for Cartridge {
       mbc: Box::new(mbc::Mbc::new()),
       cart_type: CartType::RomOnly,
       title: "".to_owned(),
+      header: None,
     }
   }
 }
@@ -107,47 +192,97 @@ impl Cartridge {
     Cartridge::default()
   }
 
-  pub fn load(&mut self, data: Box<[u8]>) -> Result<(), String> {
-    if data.len() < 0x014F {
-      return Err("invalid cartridge: too small".to_owned());
-    }
+  pub fn load(&mut self, data: Box<[u8]>) -> Result<(), CartridgeError> {
+    let header = try!(CartridgeHeader::parse(&data));
 
-    self.cart_type = match FromPrimitive::from_u8(data[0x0147]) {
+    let cart_type = match header.cart_type() {
       Some(v) => v,
-      None => {
-        return Err(format!("unknown cartridge type: {:#02x}", data[0x0147]));
-      }
+      None => return Err(CartridgeError::UnknownType(header.cart_type)),
     };
-
-    // let ram_size = match self.mbc {
-    //   MbcType::Mbc2 => 512,
-    //   _ => ram_size.as_usize(),
-    // };
-    //
-    // self.ram = vec![0; ram_size];
-
-    let new_cartridge = data[0x14b] == 0x33;
-    self.title = String::from_utf8_lossy(if new_cartridge {
-        &data[0x134..0x13f]
-      } else {
-        &data[0x134..0x144]
-      })
-      .into_owned();
-
-    let mbc_type = self.cart_type.as_mbc_type();
-    match self.mbc.load(mbc_type, data) {
-      Ok(_) => (),
-      Err(e) => return Err(e),
+    let mbc_type = match cart_type.as_mbc_type() {
+      Some(v) => v,
+      None => return Err(CartridgeError::UnsupportedMapper(cart_type)),
     };
+    try!(self.mbc.load(mbc_type, data));
+    self.cart_type = cart_type;
+    self.title = header.title.clone();
+    self.header = Some(header);
+
+    if self.cart_type.has_timer() {
+      self.mbc.enable_rtc();
+    }
+    if self.cart_type.has_rumble() {
+      self.mbc.enable_rumble();
+    }
 
     Ok(())
   }
+
+  // The header of the loaded rom.
+  pub fn header(&self) -> Option<&CartridgeHeader> {
+    self.header.as_ref()
+  }
+
+  pub fn step(&mut self) {
+    self.mbc.step();
+  }
+
+  pub fn set_rtc_mode(&mut self, mode: RtcMode) {
+    self.mbc.set_rtc_mode(mode);
+  }
+
+  // Whether the cartridge ram survives power off and should be saved.
+  pub fn has_battery(&self) -> bool {
+    self.cart_type.has_battery()
+  }
+
+  // Restores the battery backed ram, and the clock of timer carts,
+  // from a save file.
+  pub fn load_battery(&mut self, data: &[u8]) -> Result<(), String> {
+    if !self.has_battery() {
+      return Err("cartridge has no battery".to_owned());
+    }
+    self.mbc.load_ram(data)
+  }
+
+  // Returns the contents of the save file for the battery backed ram,
+  // or None if the cartridge doesn't have a battery.
+  pub fn save_battery(&mut self) -> Option<Vec<u8>> {
+    if !self.has_battery() {
+      return None;
+    }
+    Some(self.mbc.save_ram())
+  }
+
+  // Whether the battery backed ram changed since it was last saved.
+  pub fn battery_dirty(&self) -> bool {
+    self.has_battery() && self.mbc.ram_dirty()
+  }
+
+  // Whether the rumble motor is currently on. Always false for carts
+  // without one.
+  pub fn rumble(&self) -> bool {
+    self.mbc.rumble()
+  }
+
+  // Whether the infrared LED is on. Always false for carts without an
+  // infrared port.
+  pub fn ir_led(&self) -> bool {
+    self.mbc.ir_led()
+  }
+
+  // Sets where the Game Boy Camera gets its pictures from. Does nothing
+  // for other carts.
+  pub fn set_camera_source(&mut self, source: CameraSource) {
+    self.mbc.set_camera_source(source);
+  }
 }
 
 #[cfg(test)]
 mod test {
   use super::*;
   use super::CartType;
+  use super::header;
 
   #[test]
   #[ignore]
@@ -189,4 +324,39 @@ mod test {
     assert_eq!(cartridge.cart_type, CartType::RomOnly);
     assert_eq!(cartridge.title, "Opus Test       ");
   }
+
+  fn test_rom(cart_type: u8, ram_size: u8) -> Box<[u8]> {
+    let mut data = vec![0; 0x8000];
+    data[0x0147] = cart_type;
+    data[0x0149] = ram_size;
+    data[0x014D] = header::header_checksum(&data);
+    data.into_boxed_slice()
+  }
+
+  #[test]
+  fn test_load_errors() {
+    let mut cartridge = Cartridge::default();
+    assert!(cartridge.load(test_rom(0x1B, 0x03)).is_ok());
+
+    assert_eq!(cartridge.load(vec![0; 0x100].into_boxed_slice()),
+               Err(CartridgeError::BadSize(0x100)));
+    assert_eq!(cartridge.load(test_rom(0x42, 0x00)),
+               Err(CartridgeError::UnknownType(0x42)));
+    assert_eq!(cartridge.load(test_rom(0x16, 0x00)),
+               Err(CartridgeError::UnsupportedMapper(CartType::Mbc4Ram)));
+    assert_eq!(cartridge.load(test_rom(0x03, 0x09)),
+               Err(CartridgeError::UnknownRamSize(0x09)));
+
+    // A bad header checksum is only a warning, plenty of homebrew and
+    // hacked roms have one.
+    let mut rom = test_rom(0x00, 0x00);
+    rom[0x014D] ^= 0xFF;
+    let expected = rom[0x014D];
+    assert_eq!(CartridgeHeader::parse(&rom).unwrap().check_header_checksum(),
+               Err(CartridgeError::ChecksumMismatch {
+                 expected: expected,
+                 actual: expected ^ 0xFF,
+               }));
+    assert!(cartridge.load(rom).is_ok());
+  }
 }
@@ -7,6 +7,7 @@ pub enum RamSize {
   Ram8KB = 0x02,
   Ram32KB = 0x03,
   Ram128KB = 0x04,
+  Ram64KB = 0x05,
 }
 
 impl RamSize {
@@ -17,6 +18,7 @@ impl RamSize {
       RamSize::Ram8KB => 8192,
       RamSize::Ram32KB => 32768,
       RamSize::Ram128KB => 131072,
+      RamSize::Ram64KB => 65536,
     }
   }
 }
@@ -13,6 +13,7 @@ pub enum RomSize {
   Rom1MB = 0x05, // 8mbit
   Rom2MB = 0x06, // 16mbit
   Rom4MB = 0x07, // 32mbit
+  Rom8MB = 0x08, // 64mbit
   Rom1_1MB = 0x52,
   Rom1_2MB = 0x53,
   Rom1_5MB = 0x54,
@@ -29,6 +30,7 @@ impl RomSize {
       RomSize::Rom1MB => 64, // only 63 banks used by MBC1
       RomSize::Rom2MB => 128, // only 125 banks used by MBC1
       RomSize::Rom4MB => 256,
+      RomSize::Rom8MB => 512,
       RomSize::Rom1_1MB => 72,
       RomSize::Rom1_2MB => 80,
       RomSize::Rom1_5MB => 96,
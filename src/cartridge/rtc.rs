@@ -0,0 +1,343 @@
+use time;
+
+// System steps per second. The system is stepped once per machine
+// cycle (4194304hz / 4).
+pub const STEPS_PER_SECOND: u32 = 1048576;
+
+// Where the real time clock gets its time from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RtcMode {
+  // Counts emulated cycles, so the clock runs at the emulation speed
+  // and is deterministic.
+  Emulated,
+  // Follows the host's clock, even while the emulator isn't running.
+  WallClock,
+}
+
+// Size of the rtc footer VBA-M, SameBoy and BGB append to the save
+// file. Some older emulators write a 32-bit timestamp instead.
+pub const SAVE_LEN: usize = 48;
+const SAVE_LEN_32BIT: usize = 44;
+
+pub fn unix_time() -> u64 {
+  let now = time::get_time().sec;
+  if now < 0 { 0 } else { now as u64 }
+}
+
+// Where the cartridge clocks get their seconds from. In emulated mode
+// `step` reports every second of system steps, in wall clock mode
+// `elapsed` reports the seconds the host's clock moved since it was last
+// asked.
+pub struct ClockSource {
+  mode: RtcMode,
+  // Emulated mode: steps since the last second passed.
+  steps: u32,
+  // Wall clock mode: the unix time of the last update.
+  last_time: u64,
+}
+
+impl ClockSource {
+  pub fn new(mode: RtcMode) -> ClockSource {
+    ClockSource {
+      mode: mode,
+      steps: 0,
+      last_time: unix_time(),
+    }
+  }
+
+  // Time that passed in the old mode is dropped, so callers catch up
+  // with `elapsed` first.
+  pub fn set_mode(&mut self, mode: RtcMode) {
+    self.mode = mode;
+    self.last_time = unix_time();
+  }
+
+  // Called for every system step the clock is running. Returns true
+  // when a second passed.
+  pub fn step(&mut self) -> bool {
+    if self.mode != RtcMode::Emulated {
+      return false;
+    }
+
+    self.steps += 1;
+    if self.steps >= STEPS_PER_SECOND {
+      self.steps = 0;
+      return true;
+    }
+    false
+  }
+
+  // Seconds since the last update in wall clock mode, 0 otherwise.
+  pub fn elapsed(&mut self) -> u64 {
+    if self.mode != RtcMode::WallClock {
+      return 0;
+    }
+
+    let now = unix_time();
+    let elapsed = if now > self.last_time { now - self.last_time } else { 0 };
+    self.last_time = now;
+    elapsed
+  }
+
+  // Starts counting the next second from 0.
+  pub fn reset_steps(&mut self) {
+    self.steps = 0;
+  }
+
+  // The unix time the clock is current as of, for save file footers.
+  pub fn timestamp(&self) -> u64 {
+    match self.mode {
+      RtcMode::WallClock => self.last_time,
+      RtcMode::Emulated => unix_time(),
+    }
+  }
+
+  // Restores the time a save file footer was written at, the next
+  // `elapsed` returns the seconds since then.
+  pub fn load(&mut self, timestamp: u64) {
+    self.steps = 0;
+    self.last_time = timestamp;
+  }
+}
+
+// MBC3 real time clock. The registers are mapped to 0xA000-0xBFFF by
+// selecting 0x08-0x0C as the ram bank.
+//
+// 08h  RTC S   Seconds   0-59 (0-3Bh)
+// 09h  RTC M   Minutes   0-59 (0-3Bh)
+// 0Ah  RTC H   Hours     0-23 (0-17h)
+// 0Bh  RTC DL  Lower 8 bits of Day Counter (0-FFh)
+// 0Ch  RTC DH  Upper 1 bit of Day Counter, Carry Bit, Halt Flag
+//       Bit 0  Most significant bit of Day Counter (Bit 8)
+//       Bit 6  Halt (0=Active, 1=Stop Timer)
+//       Bit 7  Day Counter Carry Bit (1=Counter Overflow)
+pub struct Rtc {
+  source: ClockSource,
+
+  seconds: u8,
+  minutes: u8,
+  hours: u8,
+  days: u16,
+  halt: bool,
+  carry: bool,
+
+  // Reads always come from the latched registers.
+  latched: [u8; 5],
+  // Writing 0 and then 1 to 0x6000-0x7FFF latches the registers.
+  latch_pending: bool,
+}
+
+impl Rtc {
+  pub fn new(mode: RtcMode) -> Rtc {
+    Rtc {
+      source: ClockSource::new(mode),
+      seconds: 0,
+      minutes: 0,
+      hours: 0,
+      days: 0,
+      halt: false,
+      carry: false,
+      latched: [0; 5],
+      latch_pending: false,
+    }
+  }
+
+  pub fn set_mode(&mut self, mode: RtcMode) {
+    self.update();
+    self.source.set_mode(mode);
+  }
+
+  // Called for every system step.
+  pub fn step(&mut self) {
+    if !self.halt && self.source.step() {
+      self.advance(1);
+    }
+  }
+
+  // Catches the registers up with the host's clock.
+  fn update(&mut self) {
+    let elapsed = self.source.elapsed();
+    self.advance(elapsed);
+  }
+
+  // Moves the clock forward by the amount of seconds.
+  pub fn advance(&mut self, seconds: u64) {
+    if self.halt {
+      return;
+    }
+
+    let total = seconds + self.seconds as u64 + self.minutes as u64 * 60 +
+                self.hours as u64 * 3600 + self.days as u64 * 86400;
+
+    self.seconds = (total % 60) as u8;
+    self.minutes = (total / 60 % 60) as u8;
+    self.hours = (total / 3600 % 24) as u8;
+
+    let days = total / 86400;
+    if days > 0x1ff {
+      self.carry = true;
+    }
+    self.days = (days & 0x1ff) as u16;
+  }
+
+  fn registers(&self) -> [u8; 5] {
+    let mut dh = (self.days >> 8) as u8 & 0x01;
+    if self.halt {
+      dh |= 0x40;
+    }
+    if self.carry {
+      dh |= 0x80;
+    }
+    [self.seconds, self.minutes, self.hours, self.days as u8, dh]
+  }
+
+  pub fn latch(&mut self) {
+    self.update();
+    self.latched = self.registers();
+  }
+
+  // Handles writes to 0x6000-0x7FFF.
+  pub fn write_latch(&mut self, value: u8) {
+    if self.latch_pending && value == 0x01 {
+      self.latch();
+    }
+    self.latch_pending = value == 0x00;
+  }
+
+  // Serializes the clock in the save file footer format:
+  //
+  // 5 x u32 LE  seconds, minutes, hours, days lower, days upper/flags
+  // 5 x u32 LE  the same registers as latched
+  // u64 LE      unix timestamp of when the clock was saved
+  pub fn save(&mut self) -> Vec<u8> {
+    self.update();
+
+    let mut data = Vec::with_capacity(SAVE_LEN);
+    for &reg in self.registers().iter().chain(self.latched.iter()) {
+      data.extend_from_slice(&[reg, 0, 0, 0]);
+    }
+    let timestamp = self.source.timestamp();
+    for i in 0..8 {
+      data.push((timestamp >> (i * 8)) as u8);
+    }
+    data
+  }
+
+  // Restores the clock from the save file footer. In wall clock mode
+  // the time that passed since the save was written is added to it.
+  pub fn load(&mut self, data: &[u8]) -> Result<(), String> {
+    let timestamp_len = match data.len() {
+      SAVE_LEN => 8,
+      SAVE_LEN_32BIT => 4,
+      n => return Err(format!("rtc.load: unexpected rtc data length: {}", n)),
+    };
+
+    let mut regs = [0; 10];
+    for (i, reg) in regs.iter_mut().enumerate() {
+      *reg = data[i * 4];
+    }
+    let mut timestamp = 0u64;
+    for i in 0..timestamp_len {
+      timestamp |= (data[40 + i] as u64) << (i * 8);
+    }
+
+    self.seconds = regs[0] & 0x3f;
+    self.minutes = regs[1] & 0x3f;
+    self.hours = regs[2] & 0x1f;
+    self.days = ((regs[4] as u16 & 0x01) << 8) | regs[3] as u16;
+    self.halt = regs[4] & 0x40 != 0;
+    self.carry = regs[4] & 0x80 != 0;
+    self.latched.copy_from_slice(&regs[5..10]);
+
+    self.source.load(timestamp);
+    self.update();
+    Ok(())
+  }
+
+  // `reg` is the selected ram bank (0x08-0x0C).
+  pub fn read(&self, reg: u8) -> u8 {
+    match reg {
+      0x08...0x0C => self.latched[(reg - 0x08) as usize],
+      _ => 0xFF,
+    }
+  }
+
+  pub fn write(&mut self, reg: u8, value: u8) {
+    self.update();
+
+    match reg {
+      0x08 => {
+        self.seconds = value & 0x3f;
+        // Writing the seconds resets the sub-second counter.
+        self.source.reset_steps();
+      }
+      0x09 => self.minutes = value & 0x3f,
+      0x0A => self.hours = value & 0x1f,
+      0x0B => self.days = (self.days & 0x100) | value as u16,
+      0x0C => {
+        self.days = (self.days & 0xff) | ((value as u16 & 0x01) << 8);
+        self.halt = value & 0x40 != 0;
+        self.carry = value & 0x80 != 0;
+      }
+      _ => return,
+    }
+
+    // Keep reads consistent with what was just written.
+    self.latched[(reg - 0x08) as usize] = self.registers()[(reg - 0x08) as usize];
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_advance_and_latch() {
+    let mut rtc = Rtc::new(RtcMode::Emulated);
+    rtc.advance(511 * 86400 + 23 * 3600 + 59 * 60 + 59);
+    rtc.write_latch(0);
+    rtc.write_latch(1);
+    assert_eq!([rtc.read(0x08), rtc.read(0x09), rtc.read(0x0A), rtc.read(0x0B), rtc.read(0x0C)],
+               [59, 59, 23, 0xff, 0x01]);
+
+    // Rolls over the day counter and sets the carry bit, but the
+    // latched registers don't change until latched again.
+    rtc.advance(1);
+    assert_eq!(rtc.read(0x0C), 0x01);
+    rtc.write_latch(0);
+    rtc.write_latch(1);
+    assert_eq!([rtc.read(0x08), rtc.read(0x09), rtc.read(0x0A), rtc.read(0x0B), rtc.read(0x0C)],
+               [0, 0, 0, 0, 0x80]);
+
+    // Halted clocks don't move.
+    rtc.write(0x0C, 0x40);
+    rtc.advance(10);
+    rtc.write_latch(0);
+    rtc.write_latch(1);
+    assert_eq!(rtc.read(0x08), 0);
+  }
+
+  #[test]
+  fn test_save_and_load() {
+    let mut rtc = Rtc::new(RtcMode::WallClock);
+    rtc.write(0x0A, 5);
+    rtc.write(0x0B, 0x20);
+    let mut data = rtc.save();
+    assert_eq!(data.len(), SAVE_LEN);
+    assert_eq!(&data[8..16], &[5, 0, 0, 0, 0x20, 0, 0, 0]);
+
+    // Saved an hour ago.
+    let timestamp = unix_time() - 3600;
+    for i in 0..8 {
+      data[40 + i] = (timestamp >> (i * 8)) as u8;
+    }
+
+    let mut loaded = Rtc::new(RtcMode::WallClock);
+    loaded.load(&data).unwrap();
+    loaded.latch();
+    assert_eq!(loaded.read(0x0A), 6);
+    assert_eq!(loaded.read(0x0B), 0x20);
+
+    assert!(loaded.load(&data[..40]).is_err());
+  }
+}
@@ -0,0 +1,274 @@
+use super::rtc::{ClockSource, RtcMode};
+
+// Size of the clock footer appended to TAMA5 save files. No other
+// emulator has a TAMA5 clock footer we know of, so this one is our own
+// and other emulators will only pick up the ram from these saves.
+pub const SAVE_LEN: usize = 12;
+
+// The TAMA5 only has 32 bytes of ram.
+pub const RAM_LEN: usize = 32;
+
+const SECONDS_PER_DAY: u32 = 86400;
+
+// Registers selected by writing to 0xA001.
+const BANK_LO: u8 = 0x0;
+const BANK_HI: u8 = 0x1;
+const WRITE_LO: u8 = 0x4;
+const WRITE_HI: u8 = 0x5;
+const ADDR_HI: u8 = 0x6;
+const ADDR_LO: u8 = 0x7;
+const READ_LO: u8 = 0xC;
+const READ_HI: u8 = 0xD;
+
+// Commands, selected by the upper bits of ADDR_HI.
+const RAM_WRITE: u8 = 0x0;
+const RAM_READ: u8 = 0x1;
+const CLOCK: u8 = 0x2;
+
+// Clock commands, selected by the address.
+const TIMER_DISABLE: u8 = 0x00;
+const TIMER_ENABLE: u8 = 0x01;
+const MINUTE_WRITE: u8 = 0x04;
+const HOUR_WRITE: u8 = 0x05;
+const MINUTE_READ: u8 = 0x06;
+const HOUR_READ: u8 = 0x07;
+
+fn to_bcd(v: u32) -> u8 {
+  ((v / 10) << 4 | v % 10) as u8
+}
+
+fn from_bcd(v: u8) -> u32 {
+  (v >> 4) as u32 * 10 + (v & 0x0F) as u32
+}
+
+// Bandai TAMA5. Everything, including the rom bank, goes through two
+// addresses: 0xA001 selects a register and 0xA000 reads or writes its 4
+// bits. Ram and the clock are accessed by setting the 5 bit address in
+// ADDR_HI/ADDR_LO, with the upper bits of ADDR_HI selecting the command.
+// Writing ADDR_LO runs it, and the result is read from READ_LO/READ_HI.
+pub struct Tama5 {
+  source: ClockSource,
+
+  reg: u8,
+  registers: [u8; 8],
+
+  // Seconds since midnight.
+  time: u32,
+  timer_enabled: bool,
+}
+
+impl Tama5 {
+  pub fn new(mode: RtcMode) -> Tama5 {
+    Tama5 {
+      source: ClockSource::new(mode),
+      reg: 0,
+      registers: [0; 8],
+      time: 0,
+      timer_enabled: true,
+    }
+  }
+
+  pub fn set_mode(&mut self, mode: RtcMode) {
+    self.update();
+    self.source.set_mode(mode);
+  }
+
+  // Called for every system step.
+  pub fn step(&mut self) {
+    if self.timer_enabled && self.source.step() {
+      self.advance(1);
+    }
+  }
+
+  // Catches the clock up with the host's clock.
+  fn update(&mut self) {
+    let elapsed = self.source.elapsed();
+    if self.timer_enabled {
+      self.advance(elapsed);
+    }
+  }
+
+  pub fn advance(&mut self, seconds: u64) {
+    self.time = ((self.time as u64 + seconds) % SECONDS_PER_DAY as u64) as u32;
+  }
+
+  pub fn rom_bank(&self) -> usize {
+    (self.registers[BANK_HI as usize] as usize) << 4 | self.registers[BANK_LO as usize] as usize
+  }
+
+  fn address(&self) -> u8 {
+    (self.registers[ADDR_HI as usize] << 4 & 0x10) | self.registers[ADDR_LO as usize]
+  }
+
+  fn command(&self) -> u8 {
+    self.registers[ADDR_HI as usize] >> 1
+  }
+
+  // Reads from 0xA000-0xBFFF.
+  pub fn read(&self, ram: &[u8]) -> u8 {
+    if self.reg != READ_LO && self.reg != READ_HI {
+      // Tells the game the chip is ready.
+      return 0xF1;
+    }
+
+    let value = match self.command() {
+      RAM_READ => ram.get(self.address() as usize).cloned().unwrap_or(0),
+      CLOCK => {
+        let minutes = self.time / 60;
+        match self.address() {
+          MINUTE_READ => to_bcd(minutes % 60),
+          HOUR_READ => to_bcd(minutes / 60),
+          _ => 0,
+        }
+      }
+      _ => 0,
+    };
+
+    let nibble = if self.reg == READ_HI { value >> 4 } else { value & 0x0F };
+    0xF0 | nibble
+  }
+
+  // Writes to 0xA000-0xBFFF. Returns whether the ram or the clock
+  // changed.
+  pub fn write(&mut self, addr: u16, value: u8, ram: &mut [u8]) -> bool {
+    if addr & 0x01 != 0 {
+      self.reg = value & 0x0F;
+      return false;
+    }
+    if self.reg as usize >= self.registers.len() {
+      return false;
+    }
+    self.registers[self.reg as usize] = value & 0x0F;
+    if self.reg != ADDR_LO {
+      return false;
+    }
+
+    let data = self.registers[WRITE_HI as usize] << 4 | self.registers[WRITE_LO as usize];
+    match self.command() {
+      RAM_WRITE => {
+        if let Some(b) = ram.get_mut(self.address() as usize) {
+          *b = data;
+          return true;
+        }
+        false
+      }
+      CLOCK => {
+        self.update();
+        let (hours, minutes, seconds) = (self.time / 3600, self.time / 60 % 60, self.time % 60);
+        match self.address() {
+          TIMER_DISABLE => self.timer_enabled = false,
+          TIMER_ENABLE => self.timer_enabled = true,
+          MINUTE_WRITE => self.time = hours * 3600 + from_bcd(data) % 60 * 60 + seconds,
+          HOUR_WRITE => self.time = from_bcd(data) % 24 * 3600 + minutes * 60 + seconds,
+          _ => return false,
+        }
+        true
+      }
+      _ => false,
+    }
+  }
+
+  // Serializes the clock in the gameboy-rs specific save file footer
+  // format:
+  //
+  // u64 LE  unix timestamp of when the clock was saved
+  // u8      seconds
+  // u8      minutes
+  // u8      hours
+  // u8      1 if the timer is enabled
+  pub fn save(&mut self) -> Vec<u8> {
+    self.update();
+
+    let mut data = Vec::with_capacity(SAVE_LEN);
+    let timestamp = self.source.timestamp();
+    for i in 0..8 {
+      data.push((timestamp >> (i * 8)) as u8);
+    }
+    data.extend_from_slice(&[(self.time % 60) as u8,
+                             (self.time / 60 % 60) as u8,
+                             (self.time / 3600) as u8,
+                             self.timer_enabled as u8]);
+    data
+  }
+
+  // Restores the clock from the save file footer. In wall clock mode
+  // the time that passed since the save was written is added to it.
+  pub fn load(&mut self, data: &[u8]) -> Result<(), String> {
+    if data.len() != SAVE_LEN {
+      return Err(format!("tama5.load: unexpected clock data length: {}", data.len()));
+    }
+
+    let mut timestamp = 0u64;
+    for i in 0..8 {
+      timestamp |= (data[i] as u64) << (i * 8);
+    }
+    self.time = (data[10] as u32 % 24) * 3600 + (data[9] as u32 % 60) * 60 +
+                data[8] as u32 % 60;
+    self.timer_enabled = data[11] != 0;
+
+    self.source.load(timestamp);
+    self.update();
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use super::super::rtc::RtcMode;
+
+  fn write_reg(tama5: &mut Tama5, ram: &mut [u8], reg: u8, value: u8) {
+    tama5.write(0xA001, reg, ram);
+    tama5.write(0xA000, value, ram);
+  }
+
+  #[test]
+  fn test_ram() {
+    let mut tama5 = Tama5::new(RtcMode::Emulated);
+    let mut ram = [0; RAM_LEN];
+
+    // Write 0xA5 to 0x13.
+    write_reg(&mut tama5, &mut ram, WRITE_LO, 0x5);
+    write_reg(&mut tama5, &mut ram, WRITE_HI, 0xA);
+    write_reg(&mut tama5, &mut ram, ADDR_HI, RAM_WRITE << 1 | 0x1);
+    write_reg(&mut tama5, &mut ram, ADDR_LO, 0x3);
+    assert_eq!(ram[0x13], 0xA5);
+
+    write_reg(&mut tama5, &mut ram, ADDR_HI, RAM_READ << 1 | 0x1);
+    write_reg(&mut tama5, &mut ram, ADDR_LO, 0x3);
+    tama5.write(0xA001, READ_LO, &mut ram);
+    assert_eq!(tama5.read(&ram), 0xF5);
+    tama5.write(0xA001, READ_HI, &mut ram);
+    assert_eq!(tama5.read(&ram), 0xFA);
+
+    write_reg(&mut tama5, &mut ram, BANK_LO, 0x3);
+    write_reg(&mut tama5, &mut ram, BANK_HI, 0x1);
+    assert_eq!(tama5.rom_bank(), 0x13);
+  }
+
+  #[test]
+  fn test_clock() {
+    let mut tama5 = Tama5::new(RtcMode::Emulated);
+    let mut ram = [0; RAM_LEN];
+
+    // Set the time to 23:59.
+    write_reg(&mut tama5, &mut ram, WRITE_LO, 0x3);
+    write_reg(&mut tama5, &mut ram, WRITE_HI, 0x2);
+    write_reg(&mut tama5, &mut ram, ADDR_HI, CLOCK << 1);
+    write_reg(&mut tama5, &mut ram, ADDR_LO, HOUR_WRITE);
+    write_reg(&mut tama5, &mut ram, WRITE_LO, 0x9);
+    write_reg(&mut tama5, &mut ram, WRITE_HI, 0x5);
+    write_reg(&mut tama5, &mut ram, ADDR_LO, MINUTE_WRITE);
+    assert_eq!(tama5.time, 23 * 3600 + 59 * 60);
+
+    tama5.advance(60);
+    write_reg(&mut tama5, &mut ram, ADDR_LO, HOUR_READ);
+    tama5.write(0xA001, READ_LO, &mut ram);
+    assert_eq!(tama5.read(&ram), 0xF0);
+
+    let data = tama5.save();
+    let mut loaded = Tama5::new(RtcMode::Emulated);
+    loaded.load(&data).unwrap();
+    assert_eq!(loaded.time, tama5.time);
+  }
+}
@@ -0,0 +1,265 @@
+use std::fmt;
+
+// A decoded cheat code.
+//
+// Game Genie: ABC-DEF or ABC-DEF-GHI
+//   AB    new data
+//   FCDE  rom address, xored with 0xF000
+//   GI    optional compare byte, rotated right by 2 and xored with 0xBA.
+//         The new data is only returned if the rom has this value, so
+//         the code only affects the intended bank.
+//   H     unused
+//
+// GameShark: ABCDEFGH
+//   AB    ram bank (ignored, the value is written to whatever is mapped)
+//   CD    new data
+//   GHEF  ram address (little endian), cartridge ram, work ram or
+//         high ram
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CheatCode {
+  GameGenie {
+    addr: u16,
+    value: u8,
+    compare: Option<u8>,
+  },
+  GameShark { bank: u8, addr: u16, value: u8 },
+}
+
+fn hex_digits(code: &str) -> Result<Vec<u8>, String> {
+  code.chars()
+    .filter(|&c| c != '-')
+    .map(|c| {
+      c.to_digit(16)
+        .map(|d| d as u8)
+        .ok_or_else(|| format!("cheats: invalid character in code: {}", code))
+    })
+    .collect()
+}
+
+impl CheatCode {
+  pub fn parse(code: &str) -> Result<CheatCode, String> {
+    let d = try!(hex_digits(code));
+    match d.len() {
+      6 | 9 => {
+        let addr = ((d[5] ^ 0xF) as u16) << 12 | (d[2] as u16) << 8 | (d[3] as u16) << 4 |
+                   d[4] as u16;
+        if addr > 0x7FFF {
+          return Err(format!("cheats: game genie code {} doesn't patch the rom", code));
+        }
+        let compare = if d.len() == 9 {
+          Some((d[6] << 4 | d[8]).rotate_right(2) ^ 0xBA)
+        } else {
+          None
+        };
+        Ok(CheatCode::GameGenie {
+          addr: addr,
+          value: d[0] << 4 | d[1],
+          compare: compare,
+        })
+      }
+      8 => {
+        let addr = (d[6] as u16) << 12 | (d[7] as u16) << 8 | (d[4] as u16) << 4 | d[5] as u16;
+        match addr {
+          0xA000...0xDFFF | 0xFF80...0xFFFE => (),
+          _ => return Err(format!("cheats: gameshark code {} doesn't write to ram", code)),
+        }
+        Ok(CheatCode::GameShark {
+          bank: d[0] << 4 | d[1],
+          value: d[2] << 4 | d[3],
+          addr: addr,
+        })
+      }
+      _ => Err(format!("cheats: unknown code format: {}", code)),
+    }
+  }
+}
+
+impl fmt::Display for CheatCode {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      CheatCode::GameGenie { addr, value, compare: Some(compare) } => {
+        write!(f,
+               "game genie: {:#06x} = {:#04x} if {:#04x}",
+               addr,
+               value,
+               compare)
+      }
+      CheatCode::GameGenie { addr, value, compare: None } => {
+        write!(f, "game genie: {:#06x} = {:#04x}", addr, value)
+      }
+      CheatCode::GameShark { addr, value, .. } => {
+        write!(f, "gameshark: {:#06x} = {:#04x}", addr, value)
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cheat {
+  pub code: String,
+  pub name: String,
+  pub enabled: bool,
+  pub decoded: CheatCode,
+}
+
+// The active cheats of a system. Game Genie codes patch rom reads,
+// GameShark codes are written to ram at the start of every vblank.
+#[derive(Debug, Default)]
+pub struct Cheats {
+  cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+  pub fn new() -> Cheats {
+    Cheats::default()
+  }
+
+  // Adds an enabled cheat and returns its index.
+  pub fn add(&mut self, code: &str, name: &str) -> Result<usize, String> {
+    let decoded = try!(CheatCode::parse(code));
+    self.cheats.push(Cheat {
+      code: code.to_owned(),
+      name: name.to_owned(),
+      enabled: true,
+      decoded: decoded,
+    });
+    Ok(self.cheats.len() - 1)
+  }
+
+  pub fn remove(&mut self, index: usize) -> Result<Cheat, String> {
+    if index >= self.cheats.len() {
+      return Err(format!("cheats: no cheat with index {}", index));
+    }
+    Ok(self.cheats.remove(index))
+  }
+
+  pub fn set_enabled(&mut self, index: usize, enabled: bool) -> Result<(), String> {
+    match self.cheats.get_mut(index) {
+      Some(cheat) => {
+        cheat.enabled = enabled;
+        Ok(())
+      }
+      None => Err(format!("cheats: no cheat with index {}", index)),
+    }
+  }
+
+  pub fn list(&self) -> &[Cheat] {
+    &self.cheats
+  }
+
+  // Loads cheats from the text of a .cht file and returns how many were
+  // added. Every line is a code optionally followed by a name. Lines
+  // starting with '#' are comments, and codes starting with '-' are
+  // added disabled.
+  //
+  // # Infinite lives
+  // 01FF3CC1 Infinite lives
+  // -00A-17B-C49 Start on level 2
+  pub fn load(&mut self, text: &str) -> Result<usize, String> {
+    let mut added = 0;
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let (code, name) = match line.find(char::is_whitespace) {
+        Some(pos) => (&line[..pos], line[pos..].trim()),
+        None => (line, ""),
+      };
+      let (code, enabled) = if code.starts_with('-') {
+        (&code[1..], false)
+      } else {
+        (code, true)
+      };
+
+      let index = try!(self.add(code, name).map_err(|e| format!("line {}: {}", i + 1, e)));
+      self.cheats[index].enabled = enabled;
+      added += 1;
+    }
+    Ok(added)
+  }
+
+  // Called for every rom read (0x0000-0x7FFF) with the value in the rom.
+  pub fn patch_rom(&self, addr: u16, value: u8) -> u8 {
+    for cheat in &self.cheats {
+      if !cheat.enabled {
+        continue;
+      }
+      if let CheatCode::GameGenie { addr: a, value: v, compare } = cheat.decoded {
+        if a == addr && compare.map_or(true, |c| c == value) {
+          return v;
+        }
+      }
+    }
+    value
+  }
+
+  // The writes the enabled GameShark codes make every vblank.
+  pub fn ram_writes(&self) -> Vec<(u16, u8)> {
+    self.cheats
+      .iter()
+      .filter(|cheat| cheat.enabled)
+      .filter_map(|cheat| match cheat.decoded {
+        CheatCode::GameShark { addr, value, .. } => Some((addr, value)),
+        _ => None,
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    assert_eq!(CheatCode::parse("01FF3CC1").unwrap(),
+               CheatCode::GameShark {
+                 bank: 0x01,
+                 value: 0xFF,
+                 addr: 0xC13C,
+               });
+    assert_eq!(CheatCode::parse("3EA-5AF").unwrap(),
+               CheatCode::GameGenie {
+                 addr: 0x0A5A,
+                 value: 0x3E,
+                 compare: None,
+               });
+    // compare = ror2(0xE6) ^ 0xBA
+    assert_eq!(CheatCode::parse("3EA-5AF-E06").unwrap(),
+               CheatCode::GameGenie {
+                 addr: 0x0A5A,
+                 value: 0x3E,
+                 compare: Some(0x03),
+               });
+    assert!(CheatCode::parse("3EA-5A0").is_err());
+    // 0xFF80 and 0xFFFE are the ends of high ram, 0xFF40 (LCDC) and
+    // 0xFFFF (IE) are not ram.
+    assert!(CheatCode::parse("01FF80FF").is_ok());
+    assert!(CheatCode::parse("01FFFEFF").is_ok());
+    assert!(CheatCode::parse("01FF40FF").is_err());
+    assert!(CheatCode::parse("01FFFFFF").is_err());
+    assert!(CheatCode::parse("01FF0040").is_err());
+    assert!(CheatCode::parse("nope").is_err());
+  }
+
+  #[test]
+  fn test_cheats() {
+    let mut cheats = Cheats::new();
+    let added = cheats.load("# comment\n\n3EA-5AF-E06 Compare\n-01FF3CC1 Lives\n").unwrap();
+    assert_eq!(added, 2);
+    assert_eq!(cheats.list()[0].name, "Compare");
+
+    assert_eq!(cheats.patch_rom(0x0A5A, 0x03), 0x3E);
+    assert_eq!(cheats.patch_rom(0x0A5A, 0x10), 0x10);
+    assert!(cheats.ram_writes().is_empty());
+
+    cheats.set_enabled(1, true).unwrap();
+    assert_eq!(cheats.ram_writes(), vec![(0xC13C, 0xFF)]);
+
+    cheats.remove(0).unwrap();
+    assert_eq!(cheats.patch_rom(0x0A5A, 0x03), 0x03);
+    assert!(cheats.remove(1).is_err());
+  }
+}
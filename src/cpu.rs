@@ -5,6 +5,7 @@ use std::cmp::PartialEq;
 use super::operand::Operand;
 use super::disassembler::Instruction;
 use super::disassembler::Disassembler;
+use super::mem::MemoryIo;
 use super::system::{System, SystemCtrl};
 
 #[inline]
@@ -17,6 +18,80 @@ fn low_byte(value: u16) -> u8 {
   value as u8 & 0b11111111
 }
 
+// Machine cycles each opcode takes, including fetching it and its
+// operands. Conditional jumps, calls and returns take the not taken
+// time here, see `branch_cycles`. 0xCB is in `cb_cycles`.
+const OPCODE_CYCLES: [u32; 256] = [
+  // 0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f
+  1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1, // 0x00
+  1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1, // 0x10
+  2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 0x20
+  2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 0x30
+  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x40
+  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x50
+  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x60
+  2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1, // 0x70
+  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x80
+  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x90
+  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0xa0
+  1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0xb0
+  2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4, // 0xc0
+  2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4, // 0xd0
+  3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4, // 0xe0
+  3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4, // 0xf0
+];
+
+// Machine cycles of a conditional jump, call or return when the
+// condition holds.
+fn branch_cycles(opcode: u8) -> u32 {
+  match opcode {
+    // JR cc,e
+    0x20 | 0x28 | 0x30 | 0x38 => 3,
+    // JP cc,nn
+    0xc2 | 0xca | 0xd2 | 0xda => 4,
+    // CALL cc,nn
+    0xc4 | 0xcc | 0xd4 | 0xdc => 6,
+    // RET cc
+    0xc0 | 0xc8 | 0xd0 | 0xd8 => 5,
+    _ => OPCODE_CYCLES[opcode as usize],
+  }
+}
+
+// Machine cycles of the 0xCB prefixed opcodes, including the prefix.
+// Those on (HL) read it, and all but BIT write it back.
+fn cb_cycles(opcode: u8) -> u32 {
+  if opcode & 0x07 != 0x06 {
+    2
+  } else if opcode & 0xc0 == 0x40 {
+    3
+  } else {
+    4
+  }
+}
+
+// Memory as the instruction fetch sees it right after the HALT bug:
+// pc isn't incremented past the opcode, so its byte is read twice.
+struct HaltBugMemory<'a> {
+  memory: &'a MemoryIo,
+  pc: u16,
+}
+
+impl<'a> MemoryIo for HaltBugMemory<'a> {
+  fn read_u8(&self, addr: u16) -> Result<u8, String> {
+    if addr > self.pc {
+      self.memory.read_u8(addr - 1)
+    } else {
+      self.memory.read_u8(addr)
+    }
+  }
+}
+
+// The opcode at pc, and for 0xCB the opcode that follows.
+fn read_opcode(memory: &MemoryIo, pc: u16) -> (u8, u8) {
+  let read = |addr: u16| memory.read_u8(addr).unwrap_or(0);
+  (read(pc), read(pc.wrapping_add(1)))
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum Flag {
   Z, // zero flag
@@ -99,6 +174,12 @@ pub struct Cpu {
   machine_cycles: u32, // 1 machine cycle = 4 clock cycles
   ime: Ime,
   halt: bool,
+  // HALT with IME=0 and an interrupt already pending doesn't halt, but
+  // the next instruction fetch fails to increment pc.
+  halt_bug: bool,
+  // Set by conditional instructions when the condition holds, they
+  // take longer.
+  branch_taken: bool,
 
   pub system: Box<SystemCtrl + Send>,
   disasm: Disassembler,
@@ -124,6 +205,8 @@ impl Default for Cpu {
       machine_cycles: 0,
       ime: Ime::default(),
       halt: false,
+      halt_bug: false,
+      branch_taken: false,
       system: Box::new(System::default()),
       disasm: Disassembler::new(),
     }
@@ -175,6 +258,8 @@ impl Cpu {
     self.reg_sp = 0xfffe;
     self.reg_pc = 0x100;
 
+    // The sound registers can only be written while the APU is powered.
+    self.write_u8(0xff26, 0xf1);
     self.write_u8(0xff10, 0x80);
     self.write_u8(0xff11, 0xbf);
     self.write_u8(0xff12, 0xf3);
@@ -189,7 +274,6 @@ impl Cpu {
     self.write_u8(0xff23, 0xbf);
     self.write_u8(0xff24, 0x77);
     self.write_u8(0xff25, 0xf3);
-    self.write_u8(0xff26, 0xf1);
     self.write_u8(0xff40, 0x91);
     self.write_u8(0xff47, 0xfc);
     self.write_u8(0xff48, 0xff);
@@ -197,8 +281,8 @@ impl Cpu {
   }
 
   fn mcycle(&mut self, machine_cycles: u32) {
-    // step the system by the amount of clock cycles
-    for _ in 0..(machine_cycles * 4) {
+    // the system steps once per machine cycle
+    for _ in 0..machine_cycles {
       self.system.step();
     }
 
@@ -362,10 +446,11 @@ impl Cpu {
   }
 
   pub fn read_u8_safe(&mut self, addr: u16) -> Result<u8, String> {
-    self.mcycle(1);
     self.system.read_u8(addr)
   }
 
+  // Every memory access takes a machine cycle, the system is stepped
+  // before it so the access sees what happened during the cycle.
   pub fn read_u8(&mut self, addr: u16) -> u8 {
     self.mcycle(1);
     let val = self.system.read_u8(addr);
@@ -375,13 +460,11 @@ impl Cpu {
     }
   }
 
+  // Low byte first.
   fn read_u16(&mut self, addr: u16) -> u16 {
-    self.mcycle(2);
-    let val = self.system.read_u16(addr);
-    match val {
-      Ok(v) => v,
-      Err(e) => panic!("cpu.read_u16: {}\n{:?}", e, self),
-    }
+    let low = self.read_u8(addr) as u16;
+    let high = self.read_u8(addr.wrapping_add(1)) as u16;
+    high << 8 | low
   }
 
   fn write_u8(&mut self, addr: u16, value: u8) {
@@ -392,12 +475,10 @@ impl Cpu {
     }
   }
 
+  // Low byte first.
   fn write_u16(&mut self, addr: u16, value: u16) {
-    self.mcycle(2);
-    match self.system.write_u16(addr, value) {
-      Ok(v) => v,
-      Err(e) => panic!("cpu.write_u16: {}\n{:?}", e, self),
-    }
+    self.write_u8(addr, low_byte(value));
+    self.write_u8(addr.wrapping_add(1), high_byte(value));
   }
 
   fn write_flag(&mut self, flag: Flag, mut value: bool) {
@@ -451,6 +532,11 @@ impl Cpu {
     self.reg_pc
   }
 
+  // Machine cycles run so far, wrapping around.
+  pub fn machine_cycles(&self) -> u32 {
+    self.machine_cycles
+  }
+
   pub fn peek_at(&self, pc: u16) -> Instruction {
     match self.disasm.at(self.system.as_memoryio(), pc) {
       Ok((inst, _)) => inst,
@@ -462,27 +548,60 @@ impl Cpu {
 
   pub fn step(&mut self) -> (Instruction, u16) {
     if self.halt {
-      if self.system.has_interrupt() {
-        self.halt = false;
-      } else {
-        self.machine_cycles += 1;
-        self.system.step();
+      if !self.system.has_interrupt() {
+        self.mcycle(1);
+        return (Instruction::HALT, 0);
+      }
+
+      // A pending interrupt ends the halt even with IME=0, but is only
+      // dispatched with IME=1. Otherwise execution goes on after the
+      // HALT and the interrupt stays pending.
+      self.halt = false;
+      if self.ime.enabled() {
+        self.handle_interrupts();
         return (Instruction::HALT, 0);
       }
     }
 
-    match self.disasm.at(self.system.as_memoryio(), self.reg_pc) {
-      Ok((inst, inc)) => {
-        let pc_at_inst = self.reg_pc;
-        self.reg_pc += inc;
+    let pc_at_inst = self.reg_pc;
+    let fetched = if self.halt_bug {
+      let memory = HaltBugMemory {
+        memory: self.system.as_memoryio(),
+        pc: pc_at_inst,
+      };
+      self.disasm
+        .at(&memory, pc_at_inst)
+        .map(|(inst, inc)| (inst, inc, inc - 1, read_opcode(&memory, pc_at_inst)))
+    } else {
+      let memory = self.system.as_memoryio();
+      self.disasm
+        .at(memory, pc_at_inst)
+        .map(|(inst, inc)| (inst, inc, inc, read_opcode(memory, pc_at_inst)))
+    };
+    self.halt_bug = false;
+
+    match fetched {
+      Ok((inst, inc, pc_inc, opcode)) => {
+        self.reg_pc += pc_inc;
+
+        // Fetching the opcode and its operands takes a cycle per byte,
+        // and memory accesses take theirs while executing. The rest of
+        // the instruction's time is spent internally.
+        let start = self.machine_cycles;
+        self.mcycle(inc as u32);
 
         // use std::time::Instant;
         // let n = Instant::now();
+        self.branch_taken = false;
         self.execute_instruction(inst);
         // println!("{:?}", n.elapsed());
+        let cycles = self.instruction_cycles(opcode);
+        let spent = self.machine_cycles.wrapping_sub(start);
+        if cycles > spent {
+          self.mcycle(cycles - spent);
+        }
         self.handle_interrupts();
         self.ime.step();
-        self.system.step();
 
         (inst, pc_at_inst)
       }
@@ -492,14 +611,23 @@ impl Cpu {
     }
   }
 
+  fn instruction_cycles(&self, opcode: (u8, u8)) -> u32 {
+    match opcode {
+      (0xcb, cb) => cb_cycles(cb),
+      (op, _) if self.branch_taken => branch_cycles(op),
+      (op, _) => OPCODE_CYCLES[op as usize],
+    }
+  }
+
   fn handle_interrupts(&mut self) {
     if self.ime.enabled() {
       if let Some(int) = self.system.next_interrupt() {
         self.halt = false;
         self.ime.set_enabled(false);
+        self.mcycle(2);
         let pc = self.reg_pc;
         self.push_u16(pc);
-        self.mcycle(6);
+        self.mcycle(1);
         self.reg_pc = int.addr();
       };
     }
@@ -587,10 +715,14 @@ impl Cpu {
     val
   }
 
+  // High byte first, as the stack grows down.
   fn push_u16(&mut self, val: u16) {
-    self.reg_sp = self.reg_sp.wrapping_sub(2);
+    self.reg_sp = self.reg_sp.wrapping_sub(1);
+    let sp = self.reg_sp;
+    self.write_u8(sp, high_byte(val));
+    self.reg_sp = self.reg_sp.wrapping_sub(1);
     let sp = self.reg_sp;
-    self.write_u16(sp, val);
+    self.write_u8(sp, low_byte(val));
   }
 
   // BIT b,r
@@ -865,8 +997,6 @@ impl Cpu {
     self.write_flag(Flag::N, false);
     self.write_flag(Flag::H, (result ^ val1 ^ val2) & 0x1000 != 0);
     self.write_flag(Flag::C, carry);
-
-    self.mcycle(1);
   }
 
   // ADD SP,n
@@ -884,8 +1014,6 @@ impl Cpu {
     self.write_flag(Flag::N, false);
     self.write_flag(Flag::H, (((val1 & 0xF) + (val2 & 0xF)) & 0x10) > 0);
     self.write_flag(Flag::C, carry);
-
-    self.mcycle(2);
   }
 
   // AND n
@@ -915,11 +1043,13 @@ impl Cpu {
     if self.read_operand_u8(o1) != 0 {
       let nn = self.read_operand_u16(o2);
 
+      // Decrementing sp takes a cycle before the push.
+      self.mcycle(1);
       let pc = self.reg_pc;
       self.push_u16(pc);
       self.reg_pc = nn;
 
-      self.mcycle(2);
+      self.branch_taken = true;
     }
   }
 
@@ -930,11 +1060,10 @@ impl Cpu {
   fn inst_CALL(&mut self, o: Operand) {
     let nn = self.read_operand_u16(o);
 
+    self.mcycle(1);
     let pc = self.reg_pc;
     self.push_u16(pc);
     self.reg_pc = nn;
-
-    self.mcycle(1);
   }
 
   // CCF
@@ -1036,8 +1165,6 @@ impl Cpu {
     let val = self.read_operand_u16(o);
     let val = val.wrapping_sub(1);
     self.write_operand_u16(o, val);
-
-    self.mcycle(1);
   }
 
   // DI
@@ -1060,7 +1187,11 @@ impl Cpu {
   // Opcode: 0x76
   #[allow(non_snake_case)]
   fn inst_HALT(&mut self) {
-    self.halt = true;
+    if !self.ime.enabled() && self.system.has_interrupt() {
+      self.halt_bug = true;
+    } else {
+      self.halt = true;
+    }
     // println!("halted @ {:#06x}!", self.reg_pc);
   }
 
@@ -1087,8 +1218,6 @@ impl Cpu {
     let val = self.read_operand_u16(o);
     let val = val.wrapping_add(1);
     self.write_operand_u16(o, val);
-
-    self.mcycle(1);
   }
 
   // JP HL
@@ -1109,7 +1238,7 @@ impl Cpu {
     if self.read_operand_u8(o1) != 0 {
       let val = self.read_operand_u16(o2);
       self.reg_pc = val;
-      self.mcycle(1);
+      self.branch_taken = true;
     }
   }
 
@@ -1121,7 +1250,6 @@ impl Cpu {
     let val = self.read_operand_u8(o) as i8;
     // signed addition (can jump back)
     self.reg_pc = ((self.reg_pc as i16) + (val as i16)) as u16;
-    self.mcycle(1);
   }
 
   // JR cc,e
@@ -1134,7 +1262,7 @@ impl Cpu {
       let val = self.read_operand_u8(o2) as i8;
       // signed addition (can jump back)
       self.reg_pc = ((self.reg_pc as i16) + (val as i16)) as u16;
-      self.mcycle(1);
+      self.branch_taken = true;
     }
   }
 
@@ -1150,7 +1278,6 @@ impl Cpu {
   fn inst_LD16(&mut self, o1: Operand, o2: Operand) {
     let val = self.read_operand_u16(o2);
     self.write_operand_u16(o1, val);
-    self.mcycle(1);
   }
 
   // LDD (HL),A
@@ -1181,8 +1308,6 @@ impl Cpu {
     self.write_flag(Flag::N, false);
     self.write_flag(Flag::H, (((val1 & 0xF) + (val2 & 0xF)) & 0x10) > 0);
     self.write_flag(Flag::C, carry);
-
-    self.mcycle(1);
   }
 
   // LDI (HL),A
@@ -1241,8 +1366,8 @@ impl Cpu {
   #[allow(non_snake_case)]
   fn inst_PUSH16(&mut self, o: Operand) {
     let val = self.read_operand_u16(o);
-    self.push_u16(val);
     self.mcycle(1);
+    self.push_u16(val);
   }
 
   // RET
@@ -1251,7 +1376,6 @@ impl Cpu {
   #[allow(non_snake_case)]
   fn inst_RET(&mut self) {
     self.reg_pc = self.pop_u16();
-    self.mcycle(1);
   }
 
   // RET cc
@@ -1259,9 +1383,11 @@ impl Cpu {
   //   Page: 279
   #[allow(non_snake_case)]
   fn inst_RET_cc(&mut self, o: Operand) {
+    // Checking the condition takes a cycle, taken or not.
+    self.mcycle(1);
     if self.read_operand_u8(o) != 0 {
       self.reg_pc = self.pop_u16();
-      self.mcycle(1);
+      self.branch_taken = true;
     }
   }
 
@@ -1355,6 +1481,7 @@ impl Cpu {
   //   Page: 285
   #[allow(non_snake_case)]
   fn inst_RST(&mut self, o: Operand) {
+    self.mcycle(1);
     let pc = self.reg_pc;
     self.push_u16(pc);
     let val = self.read_operand_u8(o);
@@ -1441,6 +1568,7 @@ mod tests {
   use super::*;
   use super::super::system::SystemCtrl;
   use super::super::mem::MemoryIo;
+  use super::super::pic::Interrupt;
   use std::io::Read;
   use std::fs::File;
   use std;
@@ -1484,6 +1612,51 @@ mod tests {
     Cpu::new(Box::new(TestSystem::new()))
   }
 
+  // Counts the machine cycles the system was stepped, like a timer.
+  const TEST_COUNTER: u16 = 0xff05;
+
+  // A TestSystem that counts its steps in TEST_COUNTER and raises a
+  // vblank interrupt whenever IF and IE have a bit in common, for the
+  // timing and HALT tests.
+  struct TimingTestSystem {
+    inner: TestSystem,
+  }
+
+  impl MemoryIo for TimingTestSystem {
+    fn read_u8(&self, addr: u16) -> Result<u8, String> {
+      self.inner.read_u8(addr)
+    }
+
+    fn write_u8(&mut self, addr: u16, value: u8) -> Result<(), String> {
+      self.inner.write_u8(addr, value)
+    }
+  }
+
+  impl SystemCtrl for TimingTestSystem {
+    fn step(&mut self) {
+      let counter = &mut self.inner.ram[TEST_COUNTER as usize];
+      *counter = counter.wrapping_add(1);
+    }
+    fn as_memoryio(&self) -> &MemoryIo {
+      self as &MemoryIo
+    }
+    fn next_interrupt(&mut self) -> Option<Interrupt> {
+      if self.has_interrupt() {
+        self.inner.ram[0xff0f] = 0;
+        Some(Interrupt::Vblank)
+      } else {
+        None
+      }
+    }
+    fn has_interrupt(&self) -> bool {
+      self.inner.ram[0xff0f] & self.inner.ram[0xffff] & 0x1f != 0
+    }
+  }
+
+  fn timingcpu() -> Cpu {
+    Cpu::new(Box::new(TimingTestSystem { inner: TestSystem::new() }))
+  }
+
   struct HexVec(Vec<u8>);
   impl std::fmt::LowerHex for HexVec {
     fn fmt(&self, fmtr: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
@@ -1661,6 +1834,124 @@ mod tests {
     }
   }
 
+  #[test]
+  fn test_instruction_cycles() {
+    let mut c = testcpu();
+    let program = [0x00, // NOP
+                   0x01, 0x34, 0x12, // LD BC,0x1234
+                   0x20, 0x00, // JR NZ,0 (taken)
+                   0x28, 0x00, // JR Z,0 (not taken)
+                   0xcb, 0x11, // RL C
+                   0xcb, 0x46, // BIT 0,(HL), sets Z
+                   0xcb, 0x86, // RES 0,(HL)
+                   0xcc, 0x13, 0x00, // CALL Z,0x0013 (taken)
+                   0x00,
+                   0x00,
+                   0xc0, // RET NZ (not taken)
+                   0xc9]; // RET
+    for (i, &b) in program.iter().enumerate() {
+      c.system.write_u8(i as u16, b).unwrap();
+    }
+    c.reg_sp = 0xfffe;
+
+    for &(pc, cycles) in &[(0x00, 1), (0x01, 3), (0x04, 3), (0x06, 2), (0x08, 2), (0x0a, 3),
+                           (0x0c, 4), (0x0e, 6), (0x13, 2), (0x14, 4)] {
+      let before = c.machine_cycles;
+      let (_, at) = c.step();
+      assert_eq!(at, pc);
+      assert_eq!(c.machine_cycles - before, cycles, "instruction at {:#04x}", pc);
+    }
+    assert_eq!(c.reg_pc, 0x11);
+  }
+
+  #[test]
+  fn test_memory_access_cycles() {
+    let mut c = timingcpu();
+    let program = [0x7e, // LD A,(HL)
+                   0xf0, 0x05, // LDH A,(0x05)
+                   0xfa, 0x05, 0xff, // LD A,(0xff05)
+                   0x08, 0x05, 0xff]; // LD (0xff05),SP
+    for (i, &b) in program.iter().enumerate() {
+      c.system.write_u8(i as u16, b).unwrap();
+    }
+    c.reg_hl = TEST_COUNTER;
+
+    // Reads happen in the last cycle, after the operands are fetched.
+    for &cycle in &[2, 3, 4] {
+      c.system.write_u8(TEST_COUNTER, 0).unwrap();
+      c.step();
+      assert_eq!(c.read_reg_u8(Reg::A), cycle);
+    }
+
+    // The low byte is written in the 4th cycle, then the counter is
+    // stepped once more while the high byte is written.
+    c.system.write_u8(TEST_COUNTER, 0).unwrap();
+    c.reg_sp = 0x1020;
+    c.step();
+    assert_eq!(c.system.read_u8(TEST_COUNTER).unwrap(), 0x21);
+    assert_eq!(c.system.read_u8(TEST_COUNTER + 1).unwrap(), 0x10);
+  }
+
+  fn load_program(c: &mut Cpu, program: &[u8]) {
+    for (i, &b) in program.iter().enumerate() {
+      c.system.write_u8(i as u16, b).unwrap();
+    }
+    c.reg_sp = 0xfffe;
+    c.system.write_u8(0xffff, 0x01).unwrap();
+  }
+
+  #[test]
+  fn test_halt_bug() {
+    let mut c = timingcpu();
+    load_program(&mut c,
+                 &[0x76, // HALT
+                   0x3e, 0x14]); // LD A,0x14
+    c.system.write_u8(0xff0f, 0x01).unwrap();
+
+    // With IME=0 and an interrupt pending HALT doesn't halt, and 0x3e
+    // is read twice, making it LD A,0x3e followed by INC D.
+    c.step();
+    assert!(!c.halt);
+    c.step();
+    assert_eq!(c.read_reg_u8(Reg::A), 0x3e);
+    assert_eq!(c.reg_pc, 0x02);
+    c.step();
+    assert_eq!(c.read_reg_u8(Reg::D), 0x01);
+    assert_eq!(c.reg_pc, 0x03);
+  }
+
+  #[test]
+  fn test_halt_wake_up() {
+    let mut c = timingcpu();
+    load_program(&mut c,
+                 &[0x76, // HALT
+                   0x3c, // INC A
+                   0xfb, // EI
+                   0x76]); // HALT
+
+    c.step();
+    c.step();
+    assert!(c.halt);
+    assert_eq!(c.reg_pc, 0x01);
+
+    // With IME=0 the interrupt ends the halt without being dispatched.
+    c.system.write_u8(0xff0f, 0x01).unwrap();
+    c.step();
+    assert_eq!(c.read_reg_u8(Reg::A), 0x01);
+    assert_eq!(c.reg_pc, 0x02);
+    assert!(c.system.has_interrupt());
+
+    // With IME=1 it's dispatched as soon as the cpu wakes up.
+    c.system.write_u8(0xff0f, 0x00).unwrap();
+    c.step();
+    c.step();
+    assert!(c.halt);
+    c.system.write_u8(0xff0f, 0x01).unwrap();
+    c.step();
+    assert_eq!(c.reg_pc, 0x40);
+    assert_eq!(c.system.read_u16(0xfffc).unwrap(), 0x04);
+  }
+
   #[test]
   fn test_write_read_reg_u8() {
     let mut c = testcpu();
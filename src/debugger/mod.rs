@@ -102,6 +102,30 @@ fn debugger_app<'a, 'b>() -> App<'a, 'b> {
     .subcommand(SubCommand::with_name("breakpoints")
       .visible_alias("bp")
       .about("Prints out all the breakpoints"))
+    .subcommand(SubCommand::with_name("audio")
+      .about("Mutes or solos audio channels")
+      .arg(Arg::with_name("action")
+        .help("What to do with the channel")
+        .possible_values(&["mute", "unmute", "solo", "unsolo", "status"])
+        .required(true)
+        .index(1))
+      .arg(Arg::with_name("channel")
+        .help("The audio channel (1-4)")
+        .index(2)))
+    .subcommand(SubCommand::with_name("cheat")
+      .about("Adds, removes, enables, disables or lists cheats")
+      .arg(Arg::with_name("action")
+        .help("What to do")
+        .possible_values(&["add", "remove", "enable", "disable", "list"])
+        .required(true)
+        .index(1))
+      .arg(Arg::with_name("value")
+        .help("The Game Genie or GameShark code to add, or the index of the cheat")
+        .index(2))
+      .arg(Arg::with_name("name")
+        .help("A name for the added cheat")
+        .multiple(true)
+        .index(3)))
     .subcommand(SubCommand::with_name("exit")
       .visible_alias("quit")
       .about("Exits the debugger"))
@@ -115,6 +139,7 @@ pub struct Debugger<'a, 'b>
   app: App<'a, 'b>,
   signal: Arc<AtomicBool>,
   print_callback: Box<Fn(String)>,
+  exit_callback: Box<Fn(&mut Cpu)>,
 }
 
 impl<'a, 'b> Default for Debugger<'a, 'b> {
@@ -127,6 +152,7 @@ impl<'a, 'b> Default for Debugger<'a, 'b> {
       print_callback: Box::new(|s| {
         println!("{}", s);
       }),
+      exit_callback: Box::new(|_| {}),
     }
   }
 }
@@ -174,6 +200,13 @@ impl<'a, 'b> Debugger<'a, 'b> {
     self.print_callback = Box::new(func);
   }
 
+  // Called by the exit command before the process exits.
+  pub fn set_exit_callback<F>(&mut self, func: F)
+    where F: Fn(&mut Cpu) + 'static
+  {
+    self.exit_callback = Box::new(func);
+  }
+
   pub fn stop(&mut self) {
     self.signal.store(true, Ordering::SeqCst);
   }
@@ -220,7 +253,14 @@ impl<'a, 'b> Debugger<'a, 'b> {
           self.print(format!("{:02}: {:#06x}", i, loc));
         }
       }
+      ("audio", Some(sub_m)) => {
+        self.cmd_audio(sub_m);
+      }
+      ("cheat", Some(sub_m)) => {
+        self.cmd_cheat(sub_m);
+      }
       ("exit", Some(_)) => {
+        (self.exit_callback)(&mut self.cpu);
         exit(0);
       }
       (t, Some(_)) => {
@@ -269,6 +309,97 @@ impl<'a, 'b> Debugger<'a, 'b> {
     };
   }
 
+  fn cmd_audio<'c>(&mut self, sub_m: &ArgMatches<'c>) {
+    let action = sub_m.value_of("action").unwrap();
+
+    if action != "status" {
+      let channel = parse_num!(sub_m.value_of("channel"), {
+        self.print(format!("A channel is required\n\n{}", sub_m.usage()));
+        return;
+      });
+      if channel < 1 || channel > 4 {
+        self.print("The audio channel must be between 1 and 4".to_owned());
+        return;
+      }
+
+      match action {
+        "mute" => self.cpu.system.set_audio_channel_muted(channel - 1, true),
+        "unmute" => self.cpu.system.set_audio_channel_muted(channel - 1, false),
+        "solo" => self.cpu.system.set_audio_channel_solo(channel - 1, true),
+        "unsolo" => self.cpu.system.set_audio_channel_solo(channel - 1, false),
+        _ => unreachable!(),
+      };
+    }
+
+    for channel in 0..4 {
+      let mut state = vec![];
+      if self.cpu.system.audio_channel_muted(channel) {
+        state.push("muted");
+      }
+      if self.cpu.system.audio_channel_solo(channel) {
+        state.push("solo");
+      }
+      if state.is_empty() {
+        state.push("on");
+      }
+      self.print(format!("Channel {}: {}", channel + 1, state.join(", ")));
+    }
+  }
+
+  fn cmd_cheat<'c>(&mut self, sub_m: &ArgMatches<'c>) {
+    let action = sub_m.value_of("action").unwrap();
+
+    let result = match action {
+      "add" => {
+        let code = match sub_m.value_of("value") {
+          Some(code) => code,
+          None => {
+            self.print("A code is required".to_owned());
+            return;
+          }
+        };
+        let name = sub_m.values_of("name")
+          .map(|words| words.collect::<Vec<_>>().join(" "))
+          .unwrap_or_else(String::new);
+        self.cpu.system.add_cheat(code, &name).map(|i| format!("Added cheat {:02}", i))
+      }
+      "remove" => {
+        let i = parse_num!(sub_m.value_of("value"), {
+          self.print(format!("A cheat index is required\n\n{}", sub_m.usage()));
+          return;
+        });
+        self.cpu.system.remove_cheat(i).map(|_| format!("Removed cheat {:02}", i))
+      }
+      "enable" | "disable" => {
+        let i = parse_num!(sub_m.value_of("value"), {
+          self.print(format!("A cheat index is required\n\n{}", sub_m.usage()));
+          return;
+        });
+        self.cpu
+          .system
+          .set_cheat_enabled(i, action == "enable")
+          .map(|_| format!("Cheat {:02} {}d", i, action))
+      }
+      "list" => {
+        for (i, cheat) in self.cpu.system.cheats().iter().enumerate() {
+          self.print(format!("{:02}: [{}] {} {} ({})",
+                             i,
+                             if cheat.enabled { "x" } else { " " },
+                             cheat.code,
+                             cheat.name,
+                             cheat.decoded));
+        }
+        return;
+      }
+      _ => unreachable!(),
+    };
+
+    match result {
+      Ok(s) => self.print(s),
+      Err(e) => self.print(e),
+    }
+  }
+
   fn cmd_x<'c>(&mut self, sub_m: &ArgMatches<'c>) {
     let mut grid = Grid::new(GridOptions {
       filling: Filling::Spaces(1),
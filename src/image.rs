@@ -0,0 +1,360 @@
+// Decodes png and pgm images to 8-bit grayscale, for feeding pictures
+// to the Game Boy Camera.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use super::inflate::zlib_decompress;
+use super::patch::crc32;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// Anything bigger is scaled down to 128x112 anyway.
+const MAX_SIZE: usize = 0x4000;
+
+const PNG_GRAY: u8 = 0;
+const PNG_RGB: u8 = 2;
+const PNG_PALETTE: u8 = 3;
+const PNG_GRAY_ALPHA: u8 = 4;
+const PNG_RGBA: u8 = 6;
+
+// An 8-bit grayscale image, 0 is black and 255 white.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrayImage {
+  pub width: usize,
+  pub height: usize,
+  pub pixels: Vec<u8>,
+}
+
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+  ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
+
+fn u32_be(data: &[u8]) -> usize {
+  (data[0] as usize) << 24 | (data[1] as usize) << 16 | (data[2] as usize) << 8 |
+  data[3] as usize
+}
+
+fn check_size(width: usize, height: usize) -> Result<(), String> {
+  if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+    return Err(format!("image: unsupported image size {}x{}", width, height));
+  }
+  Ok(())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+  let p = a as i16 + b as i16 - c as i16;
+  let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+  if pa <= pb && pa <= pc {
+    a
+  } else if pb <= pc {
+    b
+  } else {
+    c
+  }
+}
+
+// Undoes the per row filters of the decompressed image data.
+fn unfilter(data: &[u8], row_len: usize, height: usize, bpp: usize) -> Result<Vec<u8>, String> {
+  if data.len() < (row_len + 1) * height {
+    return Err("image: png image data is too small".to_owned());
+  }
+
+  let mut out = vec![0u8; row_len * height];
+  for y in 0..height {
+    let filter = data[y * (row_len + 1)];
+    let src = &data[y * (row_len + 1) + 1..(y + 1) * (row_len + 1)];
+    for x in 0..row_len {
+      let a = if x >= bpp { out[y * row_len + x - bpp] } else { 0 };
+      let b = if y > 0 { out[(y - 1) * row_len + x] } else { 0 };
+      let c = if x >= bpp && y > 0 { out[(y - 1) * row_len + x - bpp] } else { 0 };
+      let predicted = match filter {
+        0 => 0,
+        1 => a,
+        2 => b,
+        3 => ((a as u16 + b as u16) / 2) as u8,
+        4 => paeth(a, b, c),
+        _ => return Err(format!("image: unknown png filter type {}", filter)),
+      };
+      out[y * row_len + x] = src[x].wrapping_add(predicted);
+    }
+  }
+  Ok(out)
+}
+
+// Decodes a non-interlaced png of any color type and bit depth.
+pub fn decode_png(data: &[u8]) -> Result<GrayImage, String> {
+  if !data.starts_with(&PNG_SIGNATURE) {
+    return Err("image: not a png file".to_owned());
+  }
+
+  let mut header = None;
+  let mut palette = vec![];
+  let mut compressed = vec![];
+  let mut pos = PNG_SIGNATURE.len();
+  loop {
+    if pos + 12 > data.len() {
+      return Err("image: unexpected end of png file".to_owned());
+    }
+    let len = u32_be(&data[pos..]);
+    if pos + 12 + len > data.len() {
+      return Err("image: unexpected end of png file".to_owned());
+    }
+    let kind = &data[pos + 4..pos + 8];
+    let chunk = &data[pos + 8..pos + 8 + len];
+    if crc32(&data[pos + 4..pos + 8 + len]) as usize != u32_be(&data[pos + 8 + len..]) {
+      return Err("image: png chunk checksum mismatch".to_owned());
+    }
+    pos += 12 + len;
+
+    if kind == b"IHDR" {
+      if len < 13 {
+        return Err("image: invalid png header".to_owned());
+      }
+      header = Some((u32_be(chunk), u32_be(&chunk[4..]), chunk[8], chunk[9], chunk[12]));
+    } else if kind == b"PLTE" {
+      palette = chunk.to_vec();
+    } else if kind == b"IDAT" {
+      compressed.extend_from_slice(chunk);
+    } else if kind == b"IEND" {
+      break;
+    }
+  }
+
+  let (width, height, depth, color_type, interlace) = match header {
+    Some(header) => header,
+    None => return Err("image: png file has no header".to_owned()),
+  };
+  try!(check_size(width, height));
+  if interlace != 0 {
+    return Err("image: interlaced pngs aren't supported".to_owned());
+  }
+  let channels = match color_type {
+    PNG_GRAY | PNG_PALETTE => 1,
+    PNG_GRAY_ALPHA => 2,
+    PNG_RGB => 3,
+    PNG_RGBA => 4,
+    _ => return Err(format!("image: unknown png color type {}", color_type)),
+  };
+  match depth {
+    1 | 2 | 4 | 8 | 16 => (),
+    _ => return Err(format!("image: invalid png bit depth {}", depth)),
+  }
+
+  let depth = depth as usize;
+  let bits_per_pixel = channels * depth;
+  let row_len = (width * bits_per_pixel + 7) / 8;
+  let bpp = if bits_per_pixel < 8 { 1 } else { bits_per_pixel / 8 };
+  // Each row starts with a filter type byte.
+  let raw_len = (row_len + 1) * height;
+  let raw = try!(zlib_decompress(&compressed, raw_len).map_err(|e| format!("image: {}", e)));
+  let rows = try!(unfilter(&raw, row_len, height, bpp));
+
+  // The sample `i` of a row, scaled to 8 bits. Palette indices aren't
+  // scaled.
+  let sample = |row: &[u8], i: usize| -> u8 {
+    match depth {
+      16 => row[i * 2],
+      8 => row[i],
+      _ => {
+        let bit = i * depth;
+        let v = (row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1) as u8;
+        if color_type == PNG_PALETTE {
+          v
+        } else {
+          (v as u32 * 255 / ((1 << depth) - 1)) as u8
+        }
+      }
+    }
+  };
+
+  let mut pixels = Vec::with_capacity(width * height);
+  for y in 0..height {
+    let row = &rows[y * row_len..(y + 1) * row_len];
+    for x in 0..width {
+      let i = x * channels;
+      let v = match color_type {
+        PNG_GRAY | PNG_GRAY_ALPHA => sample(row, i),
+        PNG_PALETTE => {
+          let index = sample(row, i) as usize * 3;
+          if index + 3 > palette.len() {
+            return Err("image: png palette index out of range".to_owned());
+          }
+          luma(palette[index], palette[index + 1], palette[index + 2])
+        }
+        _ => luma(sample(row, i), sample(row, i + 1), sample(row, i + 2)),
+      };
+      pixels.push(v);
+    }
+  }
+
+  Ok(GrayImage {
+    width: width,
+    height: height,
+    pixels: pixels,
+  })
+}
+
+// Reads the next number of a pgm file. Numbers are separated by
+// whitespace, and '#' starts a comment.
+fn pgm_number(data: &[u8], pos: &mut usize) -> Result<usize, String> {
+  loop {
+    match data.get(*pos) {
+      Some(&b'#') => {
+        while *pos < data.len() && data[*pos] != b'\n' {
+          *pos += 1;
+        }
+      }
+      Some(&b' ') | Some(&b'\t') | Some(&b'\r') | Some(&b'\n') => *pos += 1,
+      Some(_) => break,
+      None => return Err("image: unexpected end of pgm file".to_owned()),
+    }
+  }
+
+  let mut value = 0usize;
+  let start = *pos;
+  while let Some(&c @ b'0'...b'9') = data.get(*pos) {
+    value = value.saturating_mul(10).saturating_add((c - b'0') as usize);
+    *pos += 1;
+  }
+  if *pos == start {
+    return Err("image: invalid number in pgm file".to_owned());
+  }
+  Ok(value)
+}
+
+// Decodes a binary (P5) or ascii (P2) pgm.
+pub fn decode_pgm(data: &[u8]) -> Result<GrayImage, String> {
+  let binary = if data.starts_with(b"P5") {
+    true
+  } else if data.starts_with(b"P2") {
+    false
+  } else {
+    return Err("image: not a pgm file".to_owned());
+  };
+
+  let mut pos = 2;
+  let width = try!(pgm_number(data, &mut pos));
+  let height = try!(pgm_number(data, &mut pos));
+  let max = try!(pgm_number(data, &mut pos));
+  try!(check_size(width, height));
+  if max == 0 || max > 0xffff {
+    return Err(format!("image: invalid pgm maximum value {}", max));
+  }
+
+  let mut pixels = Vec::with_capacity(width * height);
+  if binary {
+    // A single whitespace character separates the header from the data.
+    pos += 1;
+    let sample_len = if max > 0xff { 2 } else { 1 };
+    if pos + width * height * sample_len > data.len() {
+      return Err("image: unexpected end of pgm file".to_owned());
+    }
+    for i in 0..width * height {
+      let v = if sample_len == 2 {
+        (data[pos + i * 2] as usize) << 8 | data[pos + i * 2 + 1] as usize
+      } else {
+        data[pos + i] as usize
+      };
+      pixels.push((v * 255 / max) as u8);
+    }
+  } else {
+    for _ in 0..width * height {
+      let v = try!(pgm_number(data, &mut pos));
+      pixels.push((v * 255 / max) as u8);
+    }
+  }
+
+  Ok(GrayImage {
+    width: width,
+    height: height,
+    pixels: pixels,
+  })
+}
+
+// Decodes a png or pgm image, detected by its magic bytes.
+pub fn decode(data: &[u8]) -> Result<GrayImage, String> {
+  if data.starts_with(&PNG_SIGNATURE) {
+    decode_png(data)
+  } else if data.starts_with(b"P5") || data.starts_with(b"P2") {
+    decode_pgm(data)
+  } else {
+    Err("image: unknown image format, expected png or pgm".to_owned())
+  }
+}
+
+pub fn load_image<P: AsRef<Path>>(path: P) -> Result<GrayImage, String> {
+  let path = path.as_ref();
+  let mut file = match File::open(path) {
+    Ok(f) => f,
+    Err(e) => return Err(format!("{}: {}", path.display(), e)),
+  };
+  let mut data = Vec::new();
+  if let Err(e) = file.read_to_end(&mut data) {
+    return Err(format!("{}: {}", path.display(), e));
+  }
+
+  decode(&data).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+impl GrayImage {
+  // Scales the image to `width` x `height` with nearest neighbour
+  // sampling.
+  pub fn resize(&self, width: usize, height: usize) -> GrayImage {
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+      for x in 0..width {
+        let sx = x * self.width / width;
+        let sy = y * self.height / height;
+        pixels.push(self.pixels[sy * self.width + sx]);
+      }
+    }
+    GrayImage {
+      width: width,
+      height: height,
+      pixels: pixels,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_pgm() {
+    let image = decode(b"P2\n# comment\n2 2\n15\n0 15\n5 10\n").unwrap();
+    assert_eq!((image.width, image.height), (2, 2));
+    assert_eq!(image.pixels, vec![0, 255, 85, 170]);
+
+    let image = decode(b"P5 2 1 255\n\x10\x20").unwrap();
+    assert_eq!(image.pixels, vec![0x10, 0x20]);
+    assert!(decode(b"P5 2 2 255\n\x10\x20").is_err());
+  }
+
+  #[test]
+  fn test_png() {
+    // 2x2 8-bit rgb: red, green, blue and white, with sub and up filters.
+    let data = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49,
+                0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x02,
+                0x00, 0x00, 0x00, 0xfd, 0xd4, 0x9a, 0x73, 0x00, 0x00, 0x00, 0x16, 0x49, 0x44,
+                0x41, 0x54, 0x78, 0x9c, 0x63, 0xfc, 0xcf, 0xc0, 0xc0, 0xf8, 0x9f, 0x81, 0x89,
+                0x91, 0xe1, 0xff, 0x7f, 0x86, 0xff, 0x00, 0x1e, 0x1c, 0x05, 0x01, 0x3b, 0x46,
+                0x0b, 0x07, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60,
+                0x82];
+    let image = decode(&data).unwrap();
+    assert_eq!((image.width, image.height), (2, 2));
+    assert_eq!(image.pixels, vec![76, 149, 29, 255]);
+
+    let mut corrupt = data;
+    corrupt[45] ^= 0xff;
+    assert!(decode(&corrupt).is_err());
+  }
+
+  #[test]
+  fn test_resize() {
+    let image = decode(b"P2 2 1 255 0 255").unwrap().resize(4, 2);
+    assert_eq!(image.pixels, vec![0, 0, 255, 255, 0, 0, 255, 255]);
+  }
+}
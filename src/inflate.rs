@@ -0,0 +1,323 @@
+// Decompresses raw deflate streams (RFC 1951), the compression used by
+// zip and gzip archives.
+
+const MAX_BITS: usize = 15;
+
+// Length codes 257-285.
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43,
+                                51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4,
+                                4, 4, 5, 5, 5, 5, 0];
+
+// Distance codes 0-29.
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385,
+                              513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385,
+                              24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9,
+                              10, 10, 11, 11, 12, 12, 13, 13];
+
+// Order the code length code lengths are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2,
+                                        14, 1, 15];
+
+struct BitReader<'a> {
+  data: &'a [u8],
+  pos: usize,
+  bit_buf: u32,
+  bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+  fn new(data: &'a [u8]) -> BitReader<'a> {
+    BitReader {
+      data: data,
+      pos: 0,
+      bit_buf: 0,
+      bit_count: 0,
+    }
+  }
+
+  // Reads `count` bits, least significant bit first.
+  fn bits(&mut self, count: u32) -> Result<u32, String> {
+    while self.bit_count < count {
+      let b = match self.data.get(self.pos) {
+        Some(&b) => b,
+        None => return Err("inflate: unexpected end of data".to_owned()),
+      };
+      self.pos += 1;
+      self.bit_buf |= (b as u32) << self.bit_count;
+      self.bit_count += 8;
+    }
+
+    let value = self.bit_buf & ((1u32 << count) - 1);
+    self.bit_buf >>= count;
+    self.bit_count -= count;
+    Ok(value)
+  }
+
+  // Throws away the rest of the current byte.
+  fn align(&mut self) {
+    self.bit_buf = 0;
+    self.bit_count = 0;
+  }
+
+  fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+    if self.pos + len > self.data.len() {
+      return Err("inflate: unexpected end of data".to_owned());
+    }
+    let bytes = &self.data[self.pos..self.pos + len];
+    self.pos += len;
+    Ok(bytes)
+  }
+}
+
+// Canonical huffman code, decoded one bit at a time.
+struct Huffman {
+  // Number of codes of each length.
+  counts: [u16; MAX_BITS + 1],
+  // Symbols ordered by code.
+  symbols: Vec<u16>,
+}
+
+impl Huffman {
+  fn new(lengths: &[u8]) -> Huffman {
+    let mut counts = [0u16; MAX_BITS + 1];
+    for &len in lengths {
+      counts[len as usize] += 1;
+    }
+
+    let mut offsets = [0u16; MAX_BITS + 1];
+    for len in 1..MAX_BITS {
+      offsets[len + 1] = offsets[len] + counts[len];
+    }
+
+    let mut symbols = vec![0; lengths.len()];
+    for (symbol, &len) in lengths.iter().enumerate() {
+      if len != 0 {
+        symbols[offsets[len as usize] as usize] = symbol as u16;
+        offsets[len as usize] += 1;
+      }
+    }
+
+    Huffman {
+      counts: counts,
+      symbols: symbols,
+    }
+  }
+
+  fn decode(&self, r: &mut BitReader) -> Result<u16, String> {
+    let mut code = 0i32;
+    let mut first = 0i32;
+    let mut index = 0i32;
+    for len in 1..MAX_BITS + 1 {
+      code |= try!(r.bits(1)) as i32;
+      let count = self.counts[len] as i32;
+      if code - first < count {
+        return Ok(self.symbols[(index + code - first) as usize]);
+      }
+      index += count;
+      first = (first + count) << 1;
+      code <<= 1;
+    }
+    Err("inflate: invalid huffman code".to_owned())
+  }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+  let mut lengths = [0u8; 288];
+  for (i, len) in lengths.iter_mut().enumerate() {
+    *len = match i {
+      0...143 => 8,
+      144...255 => 9,
+      256...279 => 7,
+      _ => 8,
+    };
+  }
+  (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_tables(r: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+  let hlit = try!(r.bits(5)) as usize + 257;
+  let hdist = try!(r.bits(5)) as usize + 1;
+  let hclen = try!(r.bits(4)) as usize + 4;
+
+  let mut code_lengths = [0u8; 19];
+  for &i in CODE_LENGTH_ORDER[..hclen].iter() {
+    code_lengths[i] = try!(r.bits(3)) as u8;
+  }
+  let code_length_table = Huffman::new(&code_lengths);
+
+  let mut lengths = vec![0u8; hlit + hdist];
+  let mut i = 0;
+  while i < lengths.len() {
+    let symbol = try!(code_length_table.decode(r));
+    let (value, repeat) = match symbol {
+      0...15 => (symbol as u8, 1),
+      16 => {
+        if i == 0 {
+          return Err("inflate: repeated length without a previous length".to_owned());
+        }
+        (lengths[i - 1], 3 + try!(r.bits(2)) as usize)
+      }
+      17 => (0, 3 + try!(r.bits(3)) as usize),
+      _ => (0, 11 + try!(r.bits(7)) as usize),
+    };
+    if i + repeat > lengths.len() {
+      return Err("inflate: too many code lengths".to_owned());
+    }
+    for len in &mut lengths[i..i + repeat] {
+      *len = value;
+    }
+    i += repeat;
+  }
+
+  Ok((Huffman::new(&lengths[..hlit]), Huffman::new(&lengths[hlit..])))
+}
+
+fn inflate_block(r: &mut BitReader,
+                 out: &mut Vec<u8>,
+                 max_len: usize,
+                 lit: &Huffman,
+                 dist: &Huffman)
+                 -> Result<(), String> {
+  loop {
+    let symbol = try!(lit.decode(r)) as usize;
+    match symbol {
+      0...255 => {
+        if out.len() >= max_len {
+          return Err("inflate: output is larger than expected".to_owned());
+        }
+        out.push(symbol as u8);
+      }
+      256 => return Ok(()),
+      257...285 => {
+        let i = symbol - 257;
+        let len = LENGTH_BASE[i] as usize + try!(r.bits(LENGTH_EXTRA[i] as u32)) as usize;
+
+        let d = try!(dist.decode(r)) as usize;
+        if d >= DIST_BASE.len() {
+          return Err("inflate: invalid distance code".to_owned());
+        }
+        let distance = DIST_BASE[d] as usize + try!(r.bits(DIST_EXTRA[d] as u32)) as usize;
+        if distance > out.len() {
+          return Err("inflate: distance too far back".to_owned());
+        }
+        if out.len() + len > max_len {
+          return Err("inflate: output is larger than expected".to_owned());
+        }
+
+        // Can overlap with the bytes being written.
+        let start = out.len() - distance;
+        for i in 0..len {
+          let b = out[start + i];
+          out.push(b);
+        }
+      }
+      _ => return Err("inflate: invalid length code".to_owned()),
+    }
+  }
+}
+
+// Decompresses a raw deflate stream. Fails if the output would be
+// larger than `max_len`, so a small stream can't expand without bound.
+pub fn inflate(data: &[u8], max_len: usize) -> Result<Vec<u8>, String> {
+  let mut r = BitReader::new(data);
+  let mut out = vec![];
+
+  loop {
+    let last = try!(r.bits(1)) == 1;
+    match try!(r.bits(2)) {
+      // Stored
+      0 => {
+        r.align();
+        let header = try!(r.bytes(4));
+        let len = header[0] as usize | (header[1] as usize) << 8;
+        let nlen = header[2] as usize | (header[3] as usize) << 8;
+        if len != !nlen & 0xffff {
+          return Err("inflate: stored block length mismatch".to_owned());
+        }
+        if out.len() + len > max_len {
+          return Err("inflate: output is larger than expected".to_owned());
+        }
+        out.extend_from_slice(try!(r.bytes(len)));
+      }
+      1 => {
+        let (lit, dist) = fixed_tables();
+        try!(inflate_block(&mut r, &mut out, max_len, &lit, &dist));
+      }
+      2 => {
+        let (lit, dist) = try!(dynamic_tables(&mut r));
+        try!(inflate_block(&mut r, &mut out, max_len, &lit, &dist));
+      }
+      _ => return Err("inflate: invalid block type".to_owned()),
+    }
+
+    if last {
+      return Ok(out);
+    }
+  }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+  let (mut a, mut b) = (1u32, 0u32);
+  for &byte in data {
+    a = (a + byte as u32) % 65521;
+    b = (b + a) % 65521;
+  }
+  b << 16 | a
+}
+
+// Decompresses a zlib stream (RFC 1950), the format png image data is
+// stored in: a 2 byte header, a deflate stream and an adler32 checksum.
+pub fn zlib_decompress(data: &[u8], max_len: usize) -> Result<Vec<u8>, String> {
+  if data.len() < 6 {
+    return Err("inflate: zlib stream is too small".to_owned());
+  }
+  let (cmf, flg) = (data[0] as u32, data[1] as u32);
+  if cmf & 0x0f != 8 || (cmf << 8 | flg) % 31 != 0 {
+    return Err("inflate: invalid zlib header".to_owned());
+  }
+  if flg & 0x20 != 0 {
+    return Err("inflate: zlib preset dictionaries aren't supported".to_owned());
+  }
+
+  let trailer = data.len() - 4;
+  let out = try!(inflate(&data[2..trailer], max_len));
+  let checksum = (data[trailer] as u32) << 24 | (data[trailer + 1] as u32) << 16 |
+                 (data[trailer + 2] as u32) << 8 | data[trailer + 3] as u32;
+  if adler32(&out) != checksum {
+    return Err("inflate: zlib checksum mismatch".to_owned());
+  }
+  Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_stored() {
+    let data = [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+    assert_eq!(inflate(&data, 3).unwrap(), b"abc".to_vec());
+    assert!(inflate(&data, 2).is_err());
+  }
+
+  #[test]
+  fn test_fixed() {
+    // "aaaaaaaaaa" compressed with zlib, without the zlib header and
+    // checksum: a literal followed by a back reference.
+    let data = [0x4b, 0x4c, 0x84, 0x01, 0x00];
+    assert_eq!(inflate(&data, 10).unwrap(), b"aaaaaaaaaa".to_vec());
+    assert!(inflate(&data, 9).is_err());
+  }
+
+  #[test]
+  fn test_zlib() {
+    let data = [0x78, 0x9c, 0x4b, 0x4c, 0x84, 0x01, 0x00, 0x14, 0xe1, 0x03, 0xcb];
+    assert_eq!(zlib_decompress(&data, 10).unwrap(), b"aaaaaaaaaa".to_vec());
+
+    let mut corrupt = data;
+    corrupt[10] ^= 0xff;
+    assert!(zlib_decompress(&corrupt, 10).is_err());
+  }
+}
@@ -35,6 +35,11 @@ pub mod debugger;
 pub mod video;
 pub mod audio;
 pub mod cartridge;
+pub mod cheats;
+pub mod image;
+pub mod inflate;
+pub mod loader;
+pub mod patch;
 pub mod linkport;
 pub mod operand;
 pub mod bios;
@@ -0,0 +1,293 @@
+use std::cmp;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use super::inflate::inflate;
+use super::patch::crc32;
+
+const ZIP_LOCAL_HEADER: u32 = 0x04034b50;
+const ZIP_CENTRAL_HEADER: u32 = 0x02014b50;
+const ZIP_END_OF_CENTRAL_DIR: u32 = 0x06054b50;
+
+const GZIP_FEXTRA: u8 = 0x04;
+const GZIP_FNAME: u8 = 0x08;
+const GZIP_FCOMMENT: u8 = 0x10;
+const GZIP_FHCRC: u8 = 0x02;
+
+// The largest cartridge rom, so archives can't decompress to more.
+const MAX_ROM_SIZE: usize = 8 * 1024 * 1024;
+
+fn u16_le(data: &[u8], pos: usize) -> Result<usize, String> {
+  if pos + 2 > data.len() {
+    return Err("loader: unexpected end of archive".to_owned());
+  }
+  Ok(data[pos] as usize | (data[pos + 1] as usize) << 8)
+}
+
+fn u32_le(data: &[u8], pos: usize) -> Result<u32, String> {
+  let lo = try!(u16_le(data, pos)) as u32;
+  let hi = try!(u16_le(data, pos + 2)) as u32;
+  Ok(hi << 16 | lo)
+}
+
+fn slice(data: &[u8], pos: usize, len: usize) -> Result<&[u8], String> {
+  if pos + len > data.len() {
+    return Err("loader: unexpected end of archive".to_owned());
+  }
+  Ok(&data[pos..pos + len])
+}
+
+fn is_rom_name(name: &str) -> bool {
+  let name = name.to_lowercase();
+  name.ends_with(".gb") || name.ends_with(".gbc")
+}
+
+// Decompresses the first .gb/.gbc file in a zip archive.
+pub fn extract_zip(data: &[u8]) -> Result<Vec<u8>, String> {
+  // The end of central directory record is at least 22 bytes, and may
+  // be followed by a comment of up to 64KB.
+  if data.len() < 22 {
+    return Err("loader: zip archive is too small".to_owned());
+  }
+  let mut end = None;
+  let min = if data.len() > 22 + 0xffff { data.len() - 22 - 0xffff } else { 0 };
+  let mut pos = data.len() - 22;
+  loop {
+    if try!(u32_le(data, pos)) == ZIP_END_OF_CENTRAL_DIR {
+      end = Some(pos);
+      break;
+    }
+    if pos == min {
+      break;
+    }
+    pos -= 1;
+  }
+  let end = match end {
+    Some(pos) => pos,
+    None => return Err("loader: couldn't find the zip central directory".to_owned()),
+  };
+
+  let entries = try!(u16_le(data, end + 10));
+  let mut pos = try!(u32_le(data, end + 16)) as usize;
+  for _ in 0..entries {
+    if try!(u32_le(data, pos)) != ZIP_CENTRAL_HEADER {
+      return Err("loader: invalid zip central directory".to_owned());
+    }
+    let name_len = try!(u16_le(data, pos + 28));
+    let extra_len = try!(u16_le(data, pos + 30));
+    let comment_len = try!(u16_le(data, pos + 32));
+    let name = String::from_utf8_lossy(try!(slice(data, pos + 46, name_len))).into_owned();
+
+    if is_rom_name(&name) {
+      let method = try!(u16_le(data, pos + 10));
+      let crc = try!(u32_le(data, pos + 16));
+      let compressed_size = try!(u32_le(data, pos + 20)) as usize;
+      let size = try!(u32_le(data, pos + 24)) as usize;
+      let local = try!(u32_le(data, pos + 42)) as usize;
+      return extract_zip_entry(data, &name, local, method, compressed_size, size, crc);
+    }
+
+    pos += 46 + name_len + extra_len + comment_len;
+  }
+
+  Err("loader: no .gb or .gbc file in the zip archive".to_owned())
+}
+
+fn extract_zip_entry(data: &[u8],
+                     name: &str,
+                     local: usize,
+                     method: usize,
+                     compressed_size: usize,
+                     size: usize,
+                     crc: u32)
+                     -> Result<Vec<u8>, String> {
+  if try!(u32_le(data, local)) != ZIP_LOCAL_HEADER {
+    return Err(format!("loader: {}: invalid zip local header", name));
+  }
+  // The local header can have a different extra field than the
+  // central directory.
+  let start = local + 30 + try!(u16_le(data, local + 26)) + try!(u16_le(data, local + 28));
+  let compressed = try!(slice(data, start, compressed_size));
+
+  let rom = match method {
+    0 => compressed.to_vec(),
+    8 => {
+      try!(inflate(compressed, cmp::min(size, MAX_ROM_SIZE))
+        .map_err(|e| format!("loader: {}: {}", name, e)))
+    }
+    _ => {
+      return Err(format!("loader: {}: unsupported zip compression method {}", name, method));
+    }
+  };
+
+  if rom.len() != size || crc32(&rom) != crc {
+    return Err(format!("loader: {}: checksum mismatch, the archive is corrupt", name));
+  }
+  Ok(rom)
+}
+
+// Decompresses a gzip file.
+pub fn extract_gzip(data: &[u8]) -> Result<Vec<u8>, String> {
+  let header = try!(slice(data, 0, 10));
+  if header[0] != 0x1f || header[1] != 0x8b || header[2] != 8 {
+    return Err("loader: not a gzip file".to_owned());
+  }
+
+  let flags = header[3];
+  let mut pos = 10;
+  if flags & GZIP_FEXTRA != 0 {
+    pos += 2 + try!(u16_le(data, pos));
+  }
+  for &flag in &[GZIP_FNAME, GZIP_FCOMMENT] {
+    if flags & flag != 0 {
+      if pos > data.len() {
+        return Err("loader: unexpected end of archive".to_owned());
+      }
+      // Zero terminated string.
+      match data[pos..].iter().position(|&b| b == 0) {
+        Some(len) => pos += len + 1,
+        None => return Err("loader: unexpected end of archive".to_owned()),
+      }
+    }
+  }
+  if flags & GZIP_FHCRC != 0 {
+    pos += 2;
+  }
+
+  if data.len() < pos + 8 {
+    return Err("loader: unexpected end of archive".to_owned());
+  }
+  let trailer = data.len() - 8;
+  let crc = try!(u32_le(data, trailer));
+  let size = try!(u32_le(data, trailer + 4));
+
+  let max_len = cmp::min(size as usize, MAX_ROM_SIZE);
+  let rom = try!(inflate(&data[pos..trailer], max_len).map_err(|e| format!("loader: {}", e)));
+  if crc32(&rom) != crc || rom.len() as u32 != size {
+    return Err("loader: checksum mismatch, the archive is corrupt".to_owned());
+  }
+  Ok(rom)
+}
+
+// Returns the rom in `data`, decompressing it if it is a zip or gzip
+// archive.
+pub fn extract_rom(data: Vec<u8>) -> Result<Vec<u8>, String> {
+  if data.starts_with(b"PK\x03\x04") {
+    extract_zip(&data)
+  } else if data.starts_with(&[0x1f, 0x8b]) {
+    extract_gzip(&data)
+  } else {
+    Ok(data)
+  }
+}
+
+// Reads a rom file, which can also be a zip or gzip archive.
+pub fn load_rom<P: AsRef<Path>>(path: P) -> Result<Box<[u8]>, String> {
+  let path = path.as_ref();
+  let mut file = match File::open(path) {
+    Ok(f) => f,
+    Err(e) => return Err(format!("{}: {}", path.display(), e)),
+  };
+  let mut data = Vec::new();
+  if let Err(e) = file.read_to_end(&mut data) {
+    return Err(format!("{}: {}", path.display(), e));
+  }
+
+  let rom = try!(extract_rom(data));
+  Ok(rom.into_boxed_slice())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  // A zip archive with, in order, "readme.txt" (stored), "stored.gb"
+  // (stored, "stored rom") and "deflated.gbc" (deflated, "deflated rom,
+  // deflated rom").
+  const ZIP: &'static [u8] = &[
+    0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21,
+    0x48, 0xf1, 0x2a, 0x9b, 0xe6, 0x09, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00,
+    0x0a, 0x00, 0x00, 0x00, 0x72, 0x65, 0x61, 0x64, 0x6d, 0x65, 0x2e, 0x74, 0x78,
+    0x74, 0x6e, 0x6f, 0x74, 0x20, 0x61, 0x20, 0x72, 0x6f, 0x6d, 0x50, 0x4b, 0x03,
+    0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x48, 0xc8, 0x81,
+    0x2d, 0x33, 0x0a, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00,
+    0x00, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64, 0x2e, 0x67, 0x62, 0x73, 0x74, 0x6f,
+    0x72, 0x65, 0x64, 0x20, 0x72, 0x6f, 0x6d, 0x50, 0x4b, 0x03, 0x04, 0x14, 0x00,
+    0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x21, 0x48, 0xe2, 0xdd, 0x17, 0xd7, 0x13,
+    0x00, 0x00, 0x00, 0x1a, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x64, 0x65,
+    0x66, 0x6c, 0x61, 0x74, 0x65, 0x64, 0x2e, 0x67, 0x62, 0x63, 0x4b, 0x49, 0x4d,
+    0xcb, 0x49, 0x2c, 0x49, 0x4d, 0x51, 0x28, 0xca, 0xcf, 0xd5, 0x51, 0x48, 0x41,
+    0xe2, 0x01, 0x00, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x03, 0x14, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x21, 0x48, 0xf1, 0x2a, 0x9b, 0xe6, 0x09, 0x00, 0x00,
+    0x00, 0x09, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x00, 0x00, 0x00, 0x00, 0x72, 0x65, 0x61,
+    0x64, 0x6d, 0x65, 0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x03,
+    0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x48, 0xc8, 0x81, 0x2d,
+    0x33, 0x0a, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x31, 0x00, 0x00,
+    0x00, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64, 0x2e, 0x67, 0x62, 0x50, 0x4b, 0x01,
+    0x02, 0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x21, 0x48,
+    0xe2, 0xdd, 0x17, 0xd7, 0x13, 0x00, 0x00, 0x00, 0x1a, 0x00, 0x00, 0x00, 0x0c,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01,
+    0x62, 0x00, 0x00, 0x00, 0x64, 0x65, 0x66, 0x6c, 0x61, 0x74, 0x65, 0x64, 0x2e,
+    0x67, 0x62, 0x63, 0x50, 0x4b, 0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00,
+    0x03, 0x00, 0xa9, 0x00, 0x00, 0x00, 0x9f, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+  // Offsets of the rom names in the central directory.
+  const STORED_CENTRAL_NAME: usize = 261;
+  const DEFLATED_CENTRAL_NAME: usize = 316;
+  // Offset of the "stored.gb" data.
+  const STORED_DATA: usize = 88;
+
+  #[test]
+  fn test_zip() {
+    // The first entry isn't a rom, so the stored .gb is picked.
+    assert_eq!(extract_rom(ZIP.to_vec()).unwrap(), b"stored rom".to_vec());
+
+    // Renaming the .gb entry in the central directory skips it for the
+    // deflated .gbc.
+    let mut data = ZIP.to_vec();
+    assert_eq!(&data[STORED_CENTRAL_NAME..STORED_CENTRAL_NAME + 9], b"stored.gb");
+    data[STORED_CENTRAL_NAME + 8] = b'x';
+    assert_eq!(extract_rom(data.clone()).unwrap(),
+               b"deflated rom, deflated rom".to_vec());
+
+    // Without any rom entries.
+    assert_eq!(&data[DEFLATED_CENTRAL_NAME..DEFLATED_CENTRAL_NAME + 12],
+               b"deflated.gbc");
+    data[DEFLATED_CENTRAL_NAME + 11] = b'x';
+    assert!(extract_rom(data).is_err());
+  }
+
+  #[test]
+  fn test_zip_corrupt() {
+    let mut data = ZIP.to_vec();
+    assert_eq!(&data[STORED_DATA..STORED_DATA + 10], b"stored rom");
+    data[STORED_DATA] ^= 0xff;
+    assert!(extract_rom(data).unwrap_err().contains("checksum mismatch"));
+
+    let mut data = ZIP.to_vec();
+    data.truncate(200);
+    assert!(extract_rom(data).is_err());
+  }
+
+  #[test]
+  fn test_gzip() {
+    let data = vec![0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0xff, 0xcb, 0x48, 0xcd,
+                    0xc9, 0xc9, 0x57, 0x28, 0xca, 0xcf, 0x05, 0x00, 0x6c, 0xe9, 0xda, 0x97, 0x09,
+                    0x00, 0x00, 0x00];
+    assert_eq!(extract_rom(data.clone()).unwrap(), b"hello rom".to_vec());
+
+    let mut corrupt = data.clone();
+    corrupt[21] ^= 0xff;
+    assert!(extract_rom(corrupt).is_err());
+
+    // Stops inflating once the output is larger than the declared size.
+    let mut short = data;
+    short[25] = 0x08;
+    assert!(extract_rom(short).unwrap_err().contains("larger than expected"));
+
+    assert_eq!(extract_rom(vec![1, 2, 3]).unwrap(), vec![1, 2, 3]);
+  }
+}
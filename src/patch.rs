@@ -0,0 +1,380 @@
+// Applies rom patches in the formats translations and romhacks are
+// distributed in.
+//
+// IPS: "PATCH", then records of a 3 byte offset and a 2 byte length
+//      followed by the data. A length of 0 is a run of a single byte
+//      (2 byte count, 1 byte value). Ends with "EOF", optionally
+//      followed by a 3 byte size to truncate the rom to.
+// UPS: "UPS1", xor deltas against the source, crc32 checked.
+// BPS: "BPS1", copy/read actions against the source and target, crc32
+//      checked.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PatchFormat {
+  Ips,
+  Ups,
+  Bps,
+}
+
+impl PatchFormat {
+  pub fn detect(patch: &[u8]) -> Option<PatchFormat> {
+    if patch.starts_with(b"PATCH") {
+      Some(PatchFormat::Ips)
+    } else if patch.starts_with(b"UPS1") {
+      Some(PatchFormat::Ups)
+    } else if patch.starts_with(b"BPS1") {
+      Some(PatchFormat::Bps)
+    } else {
+      None
+    }
+  }
+}
+
+// Applies a patch of any supported format to the rom.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+  match PatchFormat::detect(patch) {
+    Some(PatchFormat::Ips) => apply_ips(rom, patch),
+    Some(PatchFormat::Ups) => apply_ups(rom, patch),
+    Some(PatchFormat::Bps) => apply_bps(rom, patch),
+    None => Err("patch: unknown patch format".to_owned()),
+  }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+  let mut table = [0u32; 256];
+  for (i, entry) in table.iter_mut().enumerate() {
+    let mut c = i as u32;
+    for _ in 0..8 {
+      c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+    }
+    *entry = c;
+  }
+
+  let mut crc = 0xFFFFFFFFu32;
+  for &b in data {
+    crc = table[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8);
+  }
+  crc ^ 0xFFFFFFFF
+}
+
+struct Reader<'a> {
+  data: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn new(data: &'a [u8], pos: usize) -> Reader<'a> {
+    Reader {
+      data: data,
+      pos: pos,
+    }
+  }
+
+  fn u8(&mut self) -> Result<u8, String> {
+    match self.data.get(self.pos) {
+      Some(&v) => {
+        self.pos += 1;
+        Ok(v)
+      }
+      None => Err("patch: unexpected end of patch".to_owned()),
+    }
+  }
+
+  fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+    if self.pos + len > self.data.len() {
+      return Err("patch: unexpected end of patch".to_owned());
+    }
+    let bytes = &self.data[self.pos..self.pos + len];
+    self.pos += len;
+    Ok(bytes)
+  }
+
+  fn u16_be(&mut self) -> Result<usize, String> {
+    let hi = try!(self.u8()) as usize;
+    let lo = try!(self.u8()) as usize;
+    Ok(hi << 8 | lo)
+  }
+
+  fn u24_be(&mut self) -> Result<usize, String> {
+    let hi = try!(self.u16_be());
+    let lo = try!(self.u8()) as usize;
+    Ok(hi << 8 | lo)
+  }
+
+  // The variable length numbers UPS and BPS use.
+  fn varint(&mut self) -> Result<usize, String> {
+    let mut data = 0usize;
+    let mut shift = 1usize;
+    loop {
+      let x = try!(self.u8());
+      data += (x & 0x7f) as usize * shift;
+      if x & 0x80 != 0 {
+        return Ok(data);
+      }
+      if shift > 1 << 48 {
+        return Err("patch: number too large".to_owned());
+      }
+      shift <<= 7;
+      data += shift;
+    }
+  }
+}
+
+fn u32_le(data: &[u8]) -> u32 {
+  data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16 | (data[3] as u32) << 24
+}
+
+// The crc32 footer UPS and BPS share: source, target, and the patch
+// itself up to the last 4 bytes.
+struct Footer {
+  source_crc: u32,
+  target_crc: u32,
+}
+
+fn check_footer(patch: &[u8], source: &[u8]) -> Result<Footer, String> {
+  if patch.len() < 16 {
+    return Err("patch: too small".to_owned());
+  }
+  let footer = &patch[patch.len() - 12..];
+  let patch_crc = u32_le(&footer[8..12]);
+  if crc32(&patch[..patch.len() - 4]) != patch_crc {
+    return Err("patch: patch checksum mismatch, the patch is corrupt".to_owned());
+  }
+
+  let source_crc = u32_le(&footer[0..4]);
+  if crc32(source) != source_crc {
+    return Err("patch: rom checksum mismatch, the patch is for a different rom".to_owned());
+  }
+
+  Ok(Footer {
+    source_crc: source_crc,
+    target_crc: u32_le(&footer[4..8]),
+  })
+}
+
+fn check_target(footer: &Footer, target: &[u8]) -> Result<(), String> {
+  if crc32(target) != footer.target_crc {
+    return Err(format!("patch: patched rom checksum mismatch (source crc {:08x})",
+                       footer.source_crc));
+  }
+  Ok(())
+}
+
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+  if !patch.starts_with(b"PATCH") {
+    return Err("patch: not an ips patch".to_owned());
+  }
+
+  let mut out = rom.to_vec();
+  let mut r = Reader::new(patch, 5);
+  loop {
+    if try!(r.bytes(3)) == &b"EOF"[..] {
+      break;
+    }
+    r.pos -= 3;
+
+    let offset = try!(r.u24_be());
+    let len = try!(r.u16_be());
+    let (len, data) = if len == 0 {
+      let count = try!(r.u16_be());
+      let value = try!(r.u8());
+      (count, vec![value; count])
+    } else {
+      (len, try!(r.bytes(len)).to_vec())
+    };
+
+    if out.len() < offset + len {
+      out.resize(offset + len, 0);
+    }
+    out[offset..offset + len].copy_from_slice(&data);
+  }
+
+  // Truncation extension.
+  if let Ok(size) = r.u24_be() {
+    out.truncate(size);
+  }
+
+  Ok(out)
+}
+
+pub fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+  if !patch.starts_with(b"UPS1") {
+    return Err("patch: not an ups patch".to_owned());
+  }
+  let footer = try!(check_footer(patch, rom));
+
+  let mut r = Reader::new(patch, 4);
+  let source_size = try!(r.varint());
+  let target_size = try!(r.varint());
+  if source_size != rom.len() {
+    return Err(format!("patch: expected a rom of {} bytes, got {}", source_size, rom.len()));
+  }
+
+  let mut out = rom.to_vec();
+  out.resize(target_size, 0);
+
+  let end = patch.len() - 12;
+  let mut offset = 0;
+  while r.pos < end {
+    offset += try!(r.varint());
+    loop {
+      let x = try!(r.u8());
+      if offset < out.len() {
+        out[offset] ^= x;
+      }
+      offset += 1;
+      if x == 0 {
+        break;
+      }
+    }
+  }
+
+  try!(check_target(&footer, &out));
+  Ok(out)
+}
+
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+  if !patch.starts_with(b"BPS1") {
+    return Err("patch: not a bps patch".to_owned());
+  }
+  let footer = try!(check_footer(patch, rom));
+
+  let mut r = Reader::new(patch, 4);
+  let source_size = try!(r.varint());
+  let target_size = try!(r.varint());
+  let metadata_size = try!(r.varint());
+  try!(r.bytes(metadata_size));
+  if source_size != rom.len() {
+    return Err(format!("patch: expected a rom of {} bytes, got {}", source_size, rom.len()));
+  }
+
+  let out_of_bounds = || "patch: bps action out of bounds".to_owned();
+
+  let mut out = Vec::with_capacity(target_size);
+  let mut source_offset = 0isize;
+  let mut target_offset = 0isize;
+  let end = patch.len() - 12;
+  while r.pos < end {
+    let data = try!(r.varint());
+    let len = (data >> 2) + 1;
+    match data & 0x03 {
+      // SourceRead
+      0 => {
+        let start = out.len();
+        if start + len > rom.len() {
+          return Err(out_of_bounds());
+        }
+        out.extend_from_slice(&rom[start..start + len]);
+      }
+      // TargetRead
+      1 => out.extend_from_slice(try!(r.bytes(len))),
+      // SourceCopy
+      2 => {
+        let d = try!(r.varint());
+        let delta = (d >> 1) as isize;
+        source_offset += if d & 1 != 0 { -delta } else { delta };
+        if source_offset < 0 || source_offset as usize + len > rom.len() {
+          return Err(out_of_bounds());
+        }
+        let start = source_offset as usize;
+        out.extend_from_slice(&rom[start..start + len]);
+        source_offset += len as isize;
+      }
+      // TargetCopy, can overlap with the bytes it writes.
+      _ => {
+        let d = try!(r.varint());
+        let delta = (d >> 1) as isize;
+        target_offset += if d & 1 != 0 { -delta } else { delta };
+        for _ in 0..len {
+          if target_offset < 0 || target_offset as usize >= out.len() {
+            return Err(out_of_bounds());
+          }
+          let b = out[target_offset as usize];
+          out.push(b);
+          target_offset += 1;
+        }
+      }
+    }
+  }
+
+  if out.len() != target_size {
+    return Err(format!("patch: expected a {} byte rom, got {}", target_size, out.len()));
+  }
+  try!(check_target(&footer, &out));
+  Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn push_u32_le(data: &mut Vec<u8>, v: u32) {
+    data.extend_from_slice(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]);
+  }
+
+  // Adds the crc32 footer to a UPS or BPS patch.
+  fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+    push_u32_le(&mut patch, crc32(source));
+    push_u32_le(&mut patch, crc32(target));
+    let crc = crc32(&patch);
+    push_u32_le(&mut patch, crc);
+    patch
+  }
+
+  #[test]
+  fn test_crc32() {
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+  }
+
+  #[test]
+  fn test_ips() {
+    let mut patch = b"PATCH".to_vec();
+    // 2 bytes at 0x01
+    patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+    // 3 x 0xCC at 0x04, past the end of the rom
+    patch.extend_from_slice(&[0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x03, 0xCC]);
+    patch.extend_from_slice(b"EOF");
+
+    let out = apply(&[0, 1, 2, 3], &patch).unwrap();
+    assert_eq!(out, vec![0, 0xAA, 0xBB, 3, 0xCC, 0xCC, 0xCC]);
+
+    patch.extend_from_slice(&[0x00, 0x00, 0x02]);
+    let out = apply(&[0, 1, 2, 3], &patch).unwrap();
+    assert_eq!(out, vec![0, 0xAA]);
+  }
+
+  #[test]
+  fn test_ups() {
+    let source = b"abcd";
+    let target = b"abXd!";
+    // sizes 4 and 5, skip 2, xor 'c'^'X', terminator (which also
+    // moves past 'd'), skip 0, xor '!', terminator
+    let patch = vec![b'U', b'P', b'S', b'1', 0x84, 0x85, 0x82, b'c' ^ b'X', 0x00, 0x80, b'!', 0x00];
+    let patch = with_footer(patch, source, target);
+
+    assert_eq!(apply(source, &patch).unwrap(), target.to_vec());
+    assert!(apply(b"abce", &patch).is_err());
+  }
+
+  #[test]
+  fn test_bps() {
+    let source = b"abcd";
+    let target = b"abXdab";
+    let patch = vec![b'B', b'P', b'S', b'1', 0x84, 0x86, 0x80,
+                     // SourceRead 2
+                     0x84,
+                     // TargetRead 1
+                     0x81, b'X',
+                     // SourceRead 1
+                     0x80,
+                     // TargetCopy 2 from 0
+                     0x87, 0x80];
+    let patch = with_footer(patch, source, target);
+
+    assert_eq!(apply(source, &patch).unwrap(), target.to_vec());
+
+    let mut corrupt = patch.clone();
+    corrupt[8] = b'Y';
+    assert!(apply(source, &corrupt).is_err());
+  }
+}
@@ -2,7 +2,8 @@ use std::fmt;
 use md5;
 
 use super::bios::Bios;
-use super::cartridge::Cartridge;
+use super::cartridge::{Cartridge, CartridgeError, CartridgeHeader, CameraSource, RtcMode};
+use super::cheats::{Cheat, Cheats};
 use super::mem::MemoryIo;
 use super::video::{Video, Pixels};
 use super::audio::Audio;
@@ -49,7 +50,7 @@ pub trait SystemCtrl: MemoryIo {
   fn load_bios(&mut self, rom: Box<[u8]>) -> Result<(), String> {
     Ok(())
   }
-  fn load_cartridge(&mut self, rom: Box<[u8]>) -> Result<(), String> {
+  fn load_cartridge(&mut self, rom: Box<[u8]>) -> Result<(), CartridgeError> {
     Ok(())
   }
   fn step(&mut self) {}
@@ -62,6 +63,47 @@ pub trait SystemCtrl: MemoryIo {
     None
   }
   fn has_interrupt(&self) -> bool;
+  fn set_rtc_mode(&mut self, mode: RtcMode) {}
+  fn rumble_active(&self) -> bool {
+    false
+  }
+  fn set_camera_source(&mut self, source: CameraSource) {}
+  fn load_battery_ram(&mut self, data: &[u8]) -> Result<(), String> {
+    Ok(())
+  }
+  fn save_battery_ram(&mut self) -> Option<Vec<u8>> {
+    None
+  }
+  fn battery_ram_dirty(&self) -> bool {
+    false
+  }
+  fn add_cheat(&mut self, code: &str, name: &str) -> Result<usize, String> {
+    Err("cheats aren't supported".to_owned())
+  }
+  fn remove_cheat(&mut self, index: usize) -> Result<(), String> {
+    Err("cheats aren't supported".to_owned())
+  }
+  fn set_cheat_enabled(&mut self, index: usize, enabled: bool) -> Result<(), String> {
+    Err("cheats aren't supported".to_owned())
+  }
+  fn cheats(&self) -> Vec<Cheat> {
+    vec![]
+  }
+  fn load_cheats(&mut self, text: &str) -> Result<usize, String> {
+    Err("cheats aren't supported".to_owned())
+  }
+  fn set_audio_sample_rate(&mut self, rate: u32) {}
+  fn drain_audio_samples(&mut self, out: &mut Vec<(i16, i16)>) {}
+  fn set_audio_stems(&mut self, enabled: bool) {}
+  fn drain_audio_stem_samples(&mut self, channel: usize, out: &mut Vec<(i16, i16)>) {}
+  fn set_audio_channel_muted(&mut self, channel: usize, muted: bool) {}
+  fn audio_channel_muted(&self, channel: usize) -> bool {
+    false
+  }
+  fn set_audio_channel_solo(&mut self, channel: usize, solo: bool) {}
+  fn audio_channel_solo(&self, channel: usize) -> bool {
+    false
+  }
 }
 
 pub struct System {
@@ -74,6 +116,7 @@ pub struct System {
   pic: Pic,
   timer: Timer,
   gamepad: Gamepad,
+  cheats: Cheats,
 
   work_ram_0: [u8; WORK_RAM_0_LEN + 1],
   work_ram_1: [u8; WORK_RAM_1_LEN + 1],
@@ -94,6 +137,7 @@ impl Default for System {
       pic: Pic::default(),
       timer: Timer::default(),
       gamepad: Gamepad::default(),
+      cheats: Cheats::default(),
       work_ram_0: [0; WORK_RAM_0_LEN + 1],
       work_ram_1: [0; WORK_RAM_1_LEN + 1],
       high_ram: [0; HIGH_RAM_LEN + 1],
@@ -124,11 +168,13 @@ impl MemoryIo for System {
         if self.booting && addr < 0x100 {
           self.bios.read_u8(addr)
         } else {
-          self.cartridge.read_u8(addr)
+          self.cartridge.read_u8(addr).map(|v| self.cheats.patch_rom(addr, v))
         }
       }
-      // cart rom 01 | cart ram
-      0x4000...0x7fff | 0xa000...0xbfff => self.cartridge.read_u8(addr),
+      // cart rom 01
+      0x4000...0x7fff => self.cartridge.read_u8(addr).map(|v| self.cheats.patch_rom(addr, v)),
+      // cart ram
+      0xa000...0xbfff => self.cartridge.read_u8(addr),
       // video ram | sprite table
       0x8000...0x9fff | 0xfe00...0xfe9f => self.video.read_u8(addr),
       // work ram 0
@@ -267,6 +313,13 @@ impl System {
     System::default()
   }
 
+  // GameShark codes write their values at the start of every vblank.
+  fn apply_cheats(&mut self) {
+    for (addr, value) in self.cheats.ram_writes() {
+      let _ = self.write_u8(addr, value);
+    }
+  }
+
   pub fn dma_step(&mut self) {
     match self.dma.state {
       DmaState::Starting => {
@@ -295,15 +348,28 @@ impl SystemCtrl for System {
     self.bios.load(rom)
   }
 
-  fn load_cartridge(&mut self, rom: Box<[u8]>) -> Result<(), String> {
+  fn load_cartridge(&mut self, rom: Box<[u8]>) -> Result<(), CartridgeError> {
+    // The boot rom locks up on a bad header checksum, without one the
+    // rom is run anyway.
+    if self.bios.is_enabled() {
+      try!(try!(CartridgeHeader::parse(&rom)).check_header_checksum());
+    }
+
+    // The cheats belong to the previous game.
+    self.cheats = Cheats::new();
     self.cartridge.load(rom)
   }
 
   fn step(&mut self) {
     self.gamepad.step(&mut self.pic);
     self.video.step(&mut self.pic);
+    if self.video.entered_vblank() {
+      self.apply_cheats();
+    }
     self.dma_step();
     self.timer.step(&mut self.pic);
+    self.audio.step();
+    self.cartridge.step();
   }
 
   fn set_button(&mut self, btn: Button, pressed: bool) {
@@ -325,4 +391,80 @@ impl SystemCtrl for System {
   fn has_interrupt(&self) -> bool {
     self.pic.has_interrupt()
   }
+
+  fn set_rtc_mode(&mut self, mode: RtcMode) {
+    self.cartridge.set_rtc_mode(mode);
+  }
+
+  fn rumble_active(&self) -> bool {
+    self.cartridge.rumble()
+  }
+
+  fn set_camera_source(&mut self, source: CameraSource) {
+    self.cartridge.set_camera_source(source);
+  }
+
+  fn load_battery_ram(&mut self, data: &[u8]) -> Result<(), String> {
+    self.cartridge.load_battery(data)
+  }
+
+  fn save_battery_ram(&mut self) -> Option<Vec<u8>> {
+    self.cartridge.save_battery()
+  }
+
+  fn battery_ram_dirty(&self) -> bool {
+    self.cartridge.battery_dirty()
+  }
+
+  fn add_cheat(&mut self, code: &str, name: &str) -> Result<usize, String> {
+    self.cheats.add(code, name)
+  }
+
+  fn remove_cheat(&mut self, index: usize) -> Result<(), String> {
+    self.cheats.remove(index).map(|_| ())
+  }
+
+  fn set_cheat_enabled(&mut self, index: usize, enabled: bool) -> Result<(), String> {
+    self.cheats.set_enabled(index, enabled)
+  }
+
+  fn cheats(&self) -> Vec<Cheat> {
+    self.cheats.list().to_vec()
+  }
+
+  fn load_cheats(&mut self, text: &str) -> Result<usize, String> {
+    self.cheats.load(text)
+  }
+
+  fn set_audio_sample_rate(&mut self, rate: u32) {
+    self.audio.set_sample_rate(rate);
+  }
+
+  fn drain_audio_samples(&mut self, out: &mut Vec<(i16, i16)>) {
+    self.audio.drain_samples(out);
+  }
+
+  fn set_audio_stems(&mut self, enabled: bool) {
+    self.audio.set_stems_enabled(enabled);
+  }
+
+  fn drain_audio_stem_samples(&mut self, channel: usize, out: &mut Vec<(i16, i16)>) {
+    self.audio.drain_stem_samples(channel, out);
+  }
+
+  fn set_audio_channel_muted(&mut self, channel: usize, muted: bool) {
+    self.audio.set_channel_muted(channel, muted);
+  }
+
+  fn audio_channel_muted(&self, channel: usize) -> bool {
+    self.audio.channel_muted(channel)
+  }
+
+  fn set_audio_channel_solo(&mut self, channel: usize, solo: bool) {
+    self.audio.set_channel_solo(channel, solo);
+  }
+
+  fn audio_channel_solo(&self, channel: usize) -> bool {
+    self.audio.channel_solo(channel)
+  }
 }
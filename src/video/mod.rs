@@ -124,6 +124,8 @@ pub struct Video {
   sprites: [Sprite; 40],
   pub pixels: Pixels,
   dirty: bool,
+  // Set when the lcd enters vblank, until `entered_vblank` is called.
+  vblank_started: bool,
   bg_priority: [bool; SCREEN_WIDTH as usize],
 }
 
@@ -149,6 +151,7 @@ impl Default for Video {
       sprites: [Sprite::default(); 40],
       pixels: [Color::White.pixel(); SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize],
       dirty: false,
+      vblank_started: false,
       bg_priority: [false; SCREEN_WIDTH as usize],
     }
   }
@@ -340,6 +343,13 @@ impl Video {
     None
   }
 
+  // Whether the lcd entered vblank since the last call.
+  pub fn entered_vblank(&mut self) -> bool {
+    let started = self.vblank_started;
+    self.vblank_started = false;
+    started
+  }
+
   // pub fn is_dirty(&self) -> bool {
   //   self.dirty
   // }
@@ -357,6 +367,7 @@ impl Video {
         }
       }
       LcdMode::Vblank => {
+        self.vblank_started = true;
         pic.interrupt(Interrupt::Vblank);
         if self.status.contains(STAT_VBLANK_INTERRUPT) || self.status.contains(STAT_OAM_INTERRUPT) {
           pic.interrupt(Interrupt::LcdStat);